@@ -1,11 +1,13 @@
-use crate::song::{Song, SongBuilder};
+use crate::format::is_audio_file;
+use crate::library;
+use crate::song::Song;
 use crate::App;
-use color_eyre::eyre::Result;
-use memoize::memoize;
+use color_eyre::eyre::{eyre, Result};
+use crossterm::event::KeyCode;
 use permutation::permutation;
 use ratatui::{prelude::*, widgets::*};
 use std::fs::metadata;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, PartialEq)]
 pub enum FileType {
@@ -16,11 +18,18 @@ pub enum FileType {
 
 pub struct BrowserState {
     path: PathBuf,
+    /// What is listed, only the entries matching the filter while it is on
     items: Vec<String>,
+    /// Everything in the directory
+    entries: Vec<String>,
     state: ListState,
     file_type: Option<FileType>,
     current_dir: Option<PathBuf>,
     current_file: Option<PathBuf>,
+    query: String,
+    filtering: bool,
+    /// Keys go to the query while it is being typed
+    typing: bool,
 }
 
 pub struct BrowserStateBuilder {
@@ -31,8 +40,9 @@ pub struct BrowserStateBuilder {
     current_file: PathBuf,
 }
 
-fn read_dir(path: &PathBuf) -> Option<Vec<String>> {
-    let reader = std::fs::read_dir(&path).ok()?;
+fn read_dir(path: &PathBuf) -> Result<Vec<String>> {
+    let reader = std::fs::read_dir(&path)
+        .map_err(|e| eyre!("Error while reading {}: {}", path.display(), e))?;
 
     let mut items = reader
         .filter_map(|entry| {
@@ -46,29 +56,25 @@ fn read_dir(path: &PathBuf) -> Option<Vec<String>> {
 
     items.retain(|item| !item.starts_with("."));
 
-    if items.iter().all(|s| s.ends_with(".mp3")) {
+    if items.iter().all(|s| is_audio_file(&path.join(s))) {
         items = sort_by_track_number(items, path.to_path_buf())
     }
 
-    Some(items)
+    Ok(items)
 }
 
-#[memoize]
+/// Only songs already in the library are sorted, the scanner reads the tags of new ones
 fn sort_by_track_number(song_names: Vec<String>, path: PathBuf) -> Vec<String> {
     let songs = song_names
         .iter()
-        .map(|name| {
-            SongBuilder::new()
-                .from_path(&PathBuf::from(format!("{}/{}", path.display(), name)))
-                .expect("REASON")
-                .build()
-        })
+        .filter_map(|name| library::cached(&path.join(name)))
         .collect::<Vec<Song>>();
 
-    let track_numbers: Vec<&u16> = songs
+    // multi disc albums restart their track numbers on every disc
+    let track_numbers: Vec<(u16, &u16)> = songs
         .iter()
         .filter_map(|song| match song.track_number() {
-            Ok(num) => Some(num),
+            Ok(num) => Some((song.disc_number().unwrap_or_default(), num)),
             Err(_e) => None,
         })
         .collect();
@@ -98,7 +104,7 @@ impl BrowserStateBuilder {
     }
 
     pub fn path(mut self, path: PathBuf) -> Result<Self> {
-        self.items = read_dir(&path).expect("Error while reading dir");
+        self.items = read_dir(&path)?;
 
         self.path = Some(path);
 
@@ -108,11 +114,15 @@ impl BrowserStateBuilder {
     pub fn build(self) -> BrowserState {
         BrowserState {
             path: self.path.expect("BrowserStateBuilder: Path does not exist"),
+            entries: self.items.clone(),
             items: self.items,
             state: ListState::default(),
             file_type: Some(self.file_type),
             current_dir: Some(self.current_dir),
             current_file: Some(self.current_file),
+            query: String::new(),
+            filtering: false,
+            typing: false,
         }
     }
 }
@@ -124,7 +134,15 @@ impl BrowserState {
     // }
 
     pub fn update_state(&mut self) -> Result<()> {
-        self.items = read_dir(&self.path).expect("Error while reading dir in update_state");
+        self.entries = read_dir(&self.path)?;
+        self.apply_filter();
+
+        if self.items.is_empty() {
+            self.file_type = Some(FileType::NONE);
+            self.current_dir = None;
+            self.current_file = None;
+            return Ok(());
+        }
 
         let mut i = match self.state.selected() {
             Some(i) => i,
@@ -152,15 +170,14 @@ impl BrowserState {
         Ok(())
     }
 
+    /// Stops at the last entry, wrapping around loses the place in long directories
     pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
+
         let i = match self.state.selected() {
-            Some(i) => {
-                if i >= self.items.len() - 1 {
-                    0
-                } else {
-                    i + 1
-                }
-            }
+            Some(i) => (i + 1).min(self.items.len() - 1),
             None => 0,
         };
 
@@ -168,15 +185,14 @@ impl BrowserState {
         let _ = self.update_state();
     }
 
+    /// Stops at the first entry
     pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
+
         let i = match self.state.selected() {
-            Some(i) => {
-                if i == 0 {
-                    self.items.len() - 1
-                } else {
-                    i - 1
-                }
-            }
+            Some(i) => i.saturating_sub(1),
             None => 0,
         };
 
@@ -188,6 +204,7 @@ impl BrowserState {
         if self.get_file_type() == FileType::DIRECTORY {
             match &self.get_current_dir() {
                 Some(dir) => {
+                    self.stop_filter();
                     self.path.push(dir);
                     self.state.select(Some(0));
                 }
@@ -198,6 +215,109 @@ impl BrowserState {
         let _ = self.update_state();
     }
 
+    fn matches(&self, item: &str) -> bool {
+        item.to_lowercase().contains(&self.query.to_lowercase())
+    }
+
+    fn apply_filter(&mut self) {
+        self.items = match self.filtering {
+            true => self
+                .entries
+                .iter()
+                .filter(|item| self.matches(item))
+                .cloned()
+                .collect(),
+            false => self.entries.clone(),
+        };
+    }
+
+    fn selected_item(&self) -> Option<String> {
+        self.items.get(self.state.selected()?).cloned()
+    }
+
+    /// Selects `item` if it is listed, the first entry otherwise
+    fn reselect(&mut self, item: Option<String>) {
+        let i = item
+            .and_then(|item| self.items.iter().position(|i| *i == item))
+            .unwrap_or_default();
+
+        self.state.select(Some(i));
+        let _ = self.update_state();
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.typing
+    }
+
+    /// Starts typing a new filter, narrowing the list with every key
+    pub fn start_filter(&mut self) {
+        let selected = self.selected_item();
+
+        self.query.clear();
+        self.filtering = true;
+        self.typing = true;
+        self.apply_filter();
+        self.reselect(selected);
+    }
+
+    pub fn filter_key(&mut self, code: KeyCode) {
+        let selected = self.selected_item();
+
+        match code {
+            KeyCode::Esc => return self.clear_filter(),
+            KeyCode::Enter => self.typing = false,
+            KeyCode::Backspace => {
+                self.query.pop();
+            }
+            KeyCode::Char(c) => self.query.push(c),
+            _ => {}
+        }
+
+        self.apply_filter();
+        self.reselect(selected);
+    }
+
+    /// Lists every entry again, keeping the selection and the query for `n` and `N`
+    pub fn clear_filter(&mut self) {
+        let selected = self.selected_item();
+
+        self.stop_filter();
+        self.apply_filter();
+        self.reselect(selected);
+    }
+
+    fn stop_filter(&mut self) {
+        self.filtering = false;
+        self.typing = false;
+    }
+
+    pub fn is_filtering(&self) -> bool {
+        self.filtering
+    }
+
+    /// Jumps to the next entry matching the query, wrapping around at the end
+    pub fn next_match(&mut self, forward: bool) {
+        let len = self.items.len();
+
+        if self.query.is_empty() || len == 0 {
+            return;
+        }
+
+        let start = self.state.selected().unwrap_or_default();
+
+        let found = (1..=len)
+            .map(|step| match forward {
+                true => (start + step) % len,
+                false => (start + len - step % len) % len,
+            })
+            .find(|i| self.matches(&self.items[*i]));
+
+        if let Some(i) = found {
+            self.state.select(Some(i));
+            let _ = self.update_state();
+        }
+    }
+
     pub fn get_path(&self) -> PathBuf {
         self.path.clone()
     }
@@ -216,41 +336,215 @@ impl BrowserState {
         }
     }
 
+    /// The selected directory, or the one being browsed when a file is selected
+    pub fn selected_dir(&self) -> PathBuf {
+        match (self.get_file_type(), self.get_current_dir()) {
+            (FileType::DIRECTORY, Some(dir)) => self.path.join(dir),
+            _ => self.path.clone(),
+        }
+    }
+
     pub fn get_file_type(&self) -> FileType {
         self.file_type.clone().expect("Nema razlog")
     }
 
+    /// Opens the directory `file` is in, with the file selected
+    pub fn go_to(&mut self, file: &Path) -> Result<()> {
+        let (dir, name) = match (file.parent(), file.file_name()) {
+            (Some(dir), Some(name)) => (dir, name.to_string_lossy().to_string()),
+            _ => return Err(eyre!("{}: not in a directory", file.display())),
+        };
+
+        // the library can still hold songs whose directory was deleted since
+        if !dir.is_dir() {
+            return Err(eyre!("{}: directory does not exist", dir.display()));
+        }
+
+        self.stop_filter();
+        self.path = dir.to_path_buf();
+        self.state.select(Some(0));
+        self.update_state()?;
+
+        if let Some(i) = self.items.iter().position(|item| *item == name) {
+            self.state.select(Some(i));
+            self.update_state()?;
+        }
+
+        Ok(())
+    }
+
     pub fn pop(&mut self) {
+        self.stop_filter();
         self.path.pop();
         let _ = self.update_state();
     }
 }
 
-pub fn browser(app: &mut App, f: &mut Frame) -> Result<()> {
+pub fn browser(app: &mut App, f: &mut Frame, area: Rect) -> Result<()> {
     let layout = Layout::default()
         .constraints(vec![Constraint::Min(1), Constraint::Max(1)])
-        .split(f.size());
+        .split(area);
+
+    let items = app
+        .browser_state
+        .items
+        .iter()
+        .map(|item| {
+            let style = if app.marked.contains(&app.browser_state.path.join(item)) {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+
+            ListItem::new(item.as_str()).style(style)
+        })
+        .collect::<Vec<ListItem>>();
 
     f.render_stateful_widget(
-        List::new(app.browser_state.items.clone())
+        List::new(items)
             .highlight_symbol("$ ")
             .highlight_style(Style::default().bg(Color::DarkGray)),
         layout[0],
         &mut app.browser_state.state,
     );
 
+    let filter = match (app.browser_state.typing, app.browser_state.filtering) {
+        (true, _) => format!(" - filter: {}_", app.browser_state.query),
+        (false, true) => format!(" - filter: {}", app.browser_state.query),
+        (false, false) => String::new(),
+    };
+
     f.render_widget(
-        Paragraph::new(
-            app.browser_state
-                .path
-                .clone()
-                .into_os_string()
-                .into_string()
-                .ok()
-                .unwrap(),
-        ),
+        Paragraph::new(Line::from(vec![
+            Span::raw(
+                app.browser_state
+                    .path
+                    .clone()
+                    .into_os_string()
+                    .into_string()
+                    .ok()
+                    .unwrap(),
+            ),
+            Span::styled(filter, Style::default().fg(Color::Yellow)),
+        ])),
         layout[1],
     );
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn browser(names: &[&str]) -> (tempfile::TempDir, BrowserState) {
+        let dir = tempfile::tempdir().unwrap();
+
+        for name in names {
+            File::create(dir.path().join(name)).unwrap();
+        }
+
+        let mut state = BrowserStateBuilder::new()
+            .path(dir.path().to_path_buf())
+            .unwrap()
+            .build();
+        state.state.select(Some(0));
+        state.update_state().unwrap();
+
+        (dir, state)
+    }
+
+    fn type_filter(state: &mut BrowserState, query: &str) {
+        state.start_filter();
+        query
+            .chars()
+            .for_each(|c| state.filter_key(KeyCode::Char(c)));
+        state.filter_key(KeyCode::Enter);
+    }
+
+    #[test]
+    fn selected_dir_is_the_subdirectory_under_the_cursor() {
+        let (dir, mut state) = browser(&["song.txt"]);
+        std::fs::create_dir(dir.path().join("Album")).unwrap();
+        state.update_state().unwrap();
+
+        let album = state.items.iter().position(|item| item == "Album").unwrap();
+        state.state.select(Some(album));
+        state.update_state().unwrap();
+        assert_eq!(state.selected_dir(), dir.path().join("Album"));
+
+        state.state.select(Some(1 - album));
+        state.update_state().unwrap();
+        assert_eq!(state.selected_dir(), dir.path());
+    }
+
+    #[test]
+    fn moving_stops_at_the_ends() {
+        let (_dir, mut state) = browser(&["a.txt", "b.txt", "c.txt"]);
+
+        state.previous();
+        assert_eq!(state.state.selected(), Some(0));
+
+        (0..5).for_each(|_| state.next());
+        assert_eq!(state.state.selected(), Some(2));
+    }
+
+    #[test]
+    fn moving_through_an_empty_filter() {
+        let (_dir, mut state) = browser(&["a.txt", "b.txt"]);
+
+        type_filter(&mut state, "nothing");
+        assert!(state.items.is_empty());
+
+        state.next();
+        state.previous();
+        state.next_match(true);
+        assert!(state.get_file_type() == FileType::NONE);
+    }
+
+    #[test]
+    fn clearing_the_filter_keeps_the_selection() {
+        let (_dir, mut state) = browser(&["apple.txt", "banana.txt", "apricot.txt", "cherry.txt"]);
+
+        type_filter(&mut state, "CHE");
+        assert_eq!(state.items, vec!["cherry.txt"]);
+
+        state.clear_filter();
+        assert_eq!(state.items.len(), 4);
+        assert_eq!(state.selected_item().as_deref(), Some("cherry.txt"));
+        assert_eq!(state.get_current_file().as_deref(), Some("cherry.txt"));
+    }
+
+    #[test]
+    fn next_match_wraps_around_both_ways() {
+        let (_dir, mut state) = browser(&["apple.txt", "banana.txt", "apricot.txt", "cherry.txt"]);
+
+        type_filter(&mut state, "ap");
+        state.clear_filter();
+
+        let first = state.selected_item().unwrap();
+        let mut visited = Vec::new();
+
+        for _ in 0..4 {
+            state.next_match(true);
+            visited.push(state.selected_item().unwrap());
+        }
+
+        let other = visited[0].clone();
+        assert_ne!(first, other);
+        assert!(other.starts_with("ap"));
+        assert_eq!(
+            visited,
+            vec![other.clone(), first.clone(), other.clone(), first.clone()]
+        );
+
+        visited.clear();
+        for _ in 0..4 {
+            state.next_match(false);
+            visited.push(state.selected_item().unwrap());
+        }
+
+        assert_eq!(visited, vec![other.clone(), first.clone(), other, first]);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::replaygain::GainMode;
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Root of the library, scanned in the background on startup
+    pub music_dir: PathBuf,
+    pub playlists_dir: PathBuf,
+    /// Length of the crossfade in seconds
+    pub crossfade: u64,
+    /// Gain in dB for songs without ReplayGain tags
+    pub preamp: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            music_dir: music_dir(),
+            playlists_dir: music_dir().join("Playlists"),
+            crossfade: 5,
+            preamp: 0.0,
+        }
+    }
+}
+
+fn music_dir() -> PathBuf {
+    match dirs::audio_dir() {
+        Some(dir) => dir,
+        None => home::home_dir().unwrap_or_default().join("Music"),
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("rust-music-player"))
+}
+
+/// Things that are remembered across restarts, written by the app itself
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub volume: f32,
+    pub muted: bool,
+    pub crossfade: bool,
+    pub gain_mode: GainMode,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            volume: 1.0,
+            muted: false,
+            crossfade: false,
+            gain_mode: GainMode::OFF,
+        }
+    }
+}
+
+fn state_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("rust-music-player").join("state.toml"))
+}
+
+impl State {
+    /// A missing or broken state file is not worth failing over
+    pub fn load() -> Self {
+        state_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = match state_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, toml::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Reads `config.toml` from the config directory, missing keys fall back to the defaults
+    pub fn load() -> Result<Self> {
+        let path = match config_dir() {
+            Some(dir) => dir.join("config.toml"),
+            None => return Ok(Config::default()),
+        };
+
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Audio formats the player recognises
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioFormat {
+    MP3,
+    FLAC,
+    VORBIS,
+    OPUS,
+    WAV,
+    AIFF,
+    /// AAC without a container (ADTS)
+    AAC,
+    /// M4A, holding either AAC or ALAC
+    MP4,
+}
+
+impl AudioFormat {
+    /// Looks at the contents first and falls back to the extension
+    pub fn detect(path: &Path) -> Option<AudioFormat> {
+        match sniff(path) {
+            Some(format) => format,
+            None => AudioFormat::from_extension(path),
+        }
+    }
+
+    pub fn from_extension(path: &Path) -> Option<AudioFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+
+        match ext.as_str() {
+            "mp3" => Some(AudioFormat::MP3),
+            "flac" => Some(AudioFormat::FLAC),
+            "ogg" | "oga" => Some(AudioFormat::VORBIS),
+            "opus" => Some(AudioFormat::OPUS),
+            "wav" | "wave" => Some(AudioFormat::WAV),
+            "aif" | "aiff" | "aifc" => Some(AudioFormat::AIFF),
+            "aac" => Some(AudioFormat::AAC),
+            "m4a" | "m4b" | "mp4" | "alac" => Some(AudioFormat::MP4),
+            _ => None,
+        }
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.is_file() && AudioFormat::detect(path).is_some()
+}
+
+/// Vorbis, Opus and FLAC can all come in an Ogg container
+pub fn is_ogg(path: &Path) -> bool {
+    let mut header = [0; 4];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| &header == b"OggS")
+}
+
+fn sniff(path: &Path) -> Option<Option<AudioFormat>> {
+    let mut header = [0; 64];
+    let len = File::open(path).ok()?.read(&mut header).ok()?;
+
+    sniff_bytes(&header[..len])
+}
+
+/// None when the contents are not recognised, Some(None) when they are but can not be played,
+/// so an Ogg stream of another codec is not taken for Vorbis
+fn sniff_bytes(header: &[u8]) -> Option<Option<AudioFormat>> {
+    let contains = |needle: &[u8]| header.windows(needle.len()).any(|window| window == needle);
+
+    if header.starts_with(b"ID3") {
+        return Some(Some(AudioFormat::MP3));
+    }
+
+    if header.starts_with(b"fLaC") {
+        return Some(Some(AudioFormat::FLAC));
+    }
+
+    // the first page of an Ogg stream holds the codec header
+    if header.starts_with(b"OggS") {
+        if contains(b"\x01vorbis") {
+            return Some(Some(AudioFormat::VORBIS));
+        }
+        if contains(b"OpusHead") {
+            return Some(Some(AudioFormat::OPUS));
+        }
+        if contains(b"\x7fFLAC") {
+            return Some(Some(AudioFormat::FLAC));
+        }
+        return Some(None);
+    }
+
+    if header.len() >= 12 {
+        match (&header[..4], &header[8..12]) {
+            (b"RIFF", b"WAVE") => return Some(Some(AudioFormat::WAV)),
+            (b"FORM", b"AIFF") | (b"FORM", b"AIFC") => return Some(Some(AudioFormat::AIFF)),
+            (_, _) if &header[4..8] == b"ftyp" => return Some(Some(AudioFormat::MP4)),
+            _ => {}
+        }
+    }
+
+    // frame sync, ADTS has the layer bits set to zero which MPEG audio does not use
+    if header.len() >= 2 && header[0] == 0xFF {
+        if header[1] & 0xF6 == 0xF0 {
+            return Some(Some(AudioFormat::AAC));
+        }
+        if header[1] & 0xE0 == 0xE0 {
+            return Some(Some(AudioFormat::MP3));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn ogg(codec: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\x00\x02".to_vec();
+        page.extend([0; 22]);
+        page.extend(codec);
+        page
+    }
+
+    #[test]
+    fn sniffs_containers_and_codecs() {
+        let cases: [(&[u8], AudioFormat); 8] = [
+            (b"ID3\x04\x00\x00", AudioFormat::MP3),
+            (b"\xFF\xFB\x90\x64", AudioFormat::MP3),
+            (b"\xFF\xF1\x50\x80", AudioFormat::AAC),
+            (b"fLaC\x00\x00\x00\x22", AudioFormat::FLAC),
+            (b"RIFF\x24\x08\x00\x00WAVEfmt ", AudioFormat::WAV),
+            (b"FORM\x00\x00\x00\x00AIFCFVER", AudioFormat::AIFF),
+            (b"\x00\x00\x00\x20ftypM4A \x00\x00", AudioFormat::MP4),
+            (&ogg(b"\x01vorbis"), AudioFormat::VORBIS),
+        ];
+
+        for (header, format) in cases {
+            assert_eq!(sniff_bytes(header), Some(Some(format)));
+        }
+
+        assert_eq!(
+            sniff_bytes(&ogg(b"\x7fFLAC")),
+            Some(Some(AudioFormat::FLAC))
+        );
+        assert_eq!(sniff_bytes(b"not audio at all"), None);
+        assert_eq!(sniff_bytes(b""), None);
+    }
+
+    #[test]
+    fn ogg_streams_are_told_apart_by_codec() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.ogg");
+        std::fs::write(&path, ogg(b"OpusHead")).unwrap();
+        assert_eq!(AudioFormat::detect(&path), Some(AudioFormat::OPUS));
+
+        std::fs::write(&path, ogg(b"\x80theora")).unwrap();
+        assert_eq!(AudioFormat::detect(&path), None);
+        assert!(!is_audio_file(&path));
+    }
+
+    #[test]
+    fn extensions_ignore_case() {
+        let format = |name: &str| AudioFormat::from_extension(&PathBuf::from(name));
+
+        assert_eq!(format("a.MP3"), Some(AudioFormat::MP3));
+        assert_eq!(format("a.Flac"), Some(AudioFormat::FLAC));
+        assert_eq!(format("a.M4A"), Some(AudioFormat::MP4));
+        assert_eq!(format("a.aiff"), Some(AudioFormat::AIFF));
+        assert_eq!(format("a.Opus"), Some(AudioFormat::OPUS));
+        assert_eq!(format("a.txt"), None);
+        assert_eq!(format("mp3"), None);
+    }
+
+    #[test]
+    fn unrecognised_contents_fall_back_to_the_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.MP3");
+        std::fs::write(&path, b"garbage").unwrap();
+
+        assert_eq!(AudioFormat::detect(&path), Some(AudioFormat::MP3));
+    }
+}
@@ -0,0 +1,446 @@
+use rodio::source::{SeekError, UniformSourceIterator};
+use rodio::Source;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub type BoxedSource = Box<dyn Source<Item = i16> + Send>;
+
+// frame length used when the source can not tell, same as rodio's queue
+const THRESHOLD: usize = 512;
+
+/// When to start fading into the next song and for how long
+#[derive(Clone, Copy)]
+pub struct Crossfade {
+    /// Position in the current song
+    pub at: Duration,
+    pub length: Duration,
+}
+
+/// A decoded song and the gain it plays at
+pub struct Track {
+    pub source: BoxedSource,
+    pub gain: f32,
+}
+
+struct Next {
+    track: Track,
+    crossfade: Option<Crossfade>,
+}
+
+struct Shared {
+    next: Mutex<Option<Next>>,
+    // how many times the next song took over
+    started: AtomicUsize,
+    // samples played of the current song
+    samples: AtomicU64,
+    samples_per_second: AtomicU32,
+    channels: AtomicU32,
+    // gain of the current song as f32 bits
+    gain: AtomicU32,
+    // sample of the current song where the crossfade starts, u64::MAX when there is none
+    fade_at: AtomicU64,
+}
+
+struct Fade {
+    outgoing: BoxedSource,
+    gain: f32,
+    position: u64,
+    length: u64,
+}
+
+impl Fade {
+    /// Linear fade from the outgoing song to the incoming one
+    fn mix(&mut self, incoming: f32) -> f32 {
+        let outgoing = self.outgoing.next().unwrap_or(0) as f32 * self.gain;
+        let fade = self.position as f32 / self.length as f32;
+        self.position += 1;
+
+        incoming * fade + outgoing * (1.0 - fade)
+    }
+
+    fn done(&self) -> bool {
+        self.position >= self.length
+    }
+}
+
+/// Plays a song and hands over to the next one without a gap in between,
+/// or mixes the two while crossfading
+pub struct Gapless {
+    current: BoxedSource,
+    played: u64,
+    fade: Option<Fade>,
+    shared: Arc<Shared>,
+}
+
+/// Lets the player thread pick the next song while the current one plays
+pub struct GaplessHandle {
+    shared: Arc<Shared>,
+}
+
+pub fn gapless(track: Track) -> (Gapless, GaplessHandle) {
+    let shared = Arc::new(Shared {
+        next: Mutex::new(None),
+        started: AtomicUsize::new(0),
+        samples: AtomicU64::new(0),
+        samples_per_second: AtomicU32::new(samples_per_second(&track.source)),
+        channels: AtomicU32::new(track.source.channels() as u32),
+        gain: AtomicU32::new(track.gain.to_bits()),
+        fade_at: AtomicU64::new(u64::MAX),
+    });
+
+    (
+        Gapless {
+            current: track.source,
+            played: 0,
+            fade: None,
+            shared: shared.clone(),
+        },
+        GaplessHandle { shared },
+    )
+}
+
+fn samples_per_second(source: &BoxedSource) -> u32 {
+    source.sample_rate() * source.channels() as u32
+}
+
+/// Rounds down to the first sample of a frame, so channels never swap places
+fn frame_start(sample: u64, channels: u32) -> u64 {
+    sample - sample % channels.max(1) as u64
+}
+
+impl GaplessHandle {
+    /// Replaces the song that plays next. Does nothing and returns false when another song
+    /// took over since `started`, the caller has to catch up first.
+    pub fn set_next(
+        &self,
+        track: Option<Track>,
+        crossfade: Option<Crossfade>,
+        started: usize,
+    ) -> bool {
+        let mut next = self.shared.next.lock().unwrap();
+
+        if self.started() != started {
+            return false;
+        }
+
+        let samples_per_second = self.shared.samples_per_second.load(Ordering::Relaxed);
+        let channels = self.shared.channels.load(Ordering::Relaxed);
+        let fade_at = match (&track, crossfade) {
+            (Some(_), Some(crossfade)) => frame_start(
+                (crossfade.at.as_secs_f64() * samples_per_second as f64) as u64,
+                channels,
+            ),
+            _ => u64::MAX,
+        };
+        self.shared.fade_at.store(fade_at, Ordering::Relaxed);
+
+        *next = track.map(|track| Next { track, crossfade });
+        true
+    }
+
+    /// Changes the gain of the song that is playing
+    pub fn set_gain(&self, gain: f32) {
+        self.shared.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn started(&self) -> usize {
+        self.shared.started.load(Ordering::SeqCst)
+    }
+
+    /// Position in the song that is playing
+    pub fn position(&self) -> Duration {
+        let samples = self.shared.samples.load(Ordering::Relaxed);
+        let samples_per_second = self.shared.samples_per_second.load(Ordering::Relaxed);
+
+        if samples_per_second == 0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(samples as f64 / samples_per_second as f64)
+    }
+}
+
+impl Iterator for Gapless {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.fade.is_none() && self.played >= self.shared.fade_at.load(Ordering::Relaxed) {
+            self.start_crossfade();
+        }
+
+        loop {
+            if let Some(sample) = self.current.next() {
+                self.played += 1;
+                self.shared.samples.store(self.played, Ordering::Relaxed);
+
+                let gain = f32::from_bits(self.shared.gain.load(Ordering::Relaxed));
+
+                let sample = match self.fade.as_mut() {
+                    Some(fade) => fade.mix(sample as f32 * gain),
+                    None if gain == 1.0 => return Some(sample),
+                    None => sample as f32 * gain,
+                };
+
+                if self.fade.as_ref().is_some_and(|fade| fade.done()) {
+                    self.fade = None;
+                }
+
+                return Some(sample.clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+            }
+
+            self.fade = None;
+
+            // started changes while holding the lock so set_next can not miss it
+            let shared = self.shared.clone();
+            let mut next = shared.next.lock().unwrap();
+            let track = next.take()?.track;
+
+            self.shared
+                .samples_per_second
+                .store(samples_per_second(&track.source), Ordering::Relaxed);
+            self.shared
+                .channels
+                .store(track.source.channels() as u32, Ordering::Relaxed);
+            self.current = track.source;
+            self.took_over(track.gain);
+        }
+    }
+}
+
+impl Gapless {
+    /// Counts from the start of the song that took over
+    fn took_over(&mut self, gain: f32) {
+        self.played = 0;
+        self.shared.gain.store(gain.to_bits(), Ordering::Relaxed);
+        self.shared.samples.store(0, Ordering::Relaxed);
+        self.shared.fade_at.store(u64::MAX, Ordering::Relaxed);
+        self.shared.started.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The next song starts under the current one, converted to its format so they can be mixed
+    fn start_crossfade(&mut self) {
+        let shared = self.shared.clone();
+        let mut next = shared.next.lock().unwrap();
+
+        let (track, crossfade) = match next.take() {
+            Some(Next {
+                track,
+                crossfade: Some(crossfade),
+            }) => (track, crossfade),
+            other => {
+                *next = other;
+                self.shared.fade_at.store(u64::MAX, Ordering::Relaxed);
+                return;
+            }
+        };
+
+        let samples_per_second = self.shared.samples_per_second.load(Ordering::Relaxed);
+        let length = (crossfade.length.as_secs_f64() * samples_per_second as f64) as u64;
+
+        let incoming: BoxedSource = Box::new(UniformSourceIterator::<BoxedSource, i16>::new(
+            track.source,
+            self.current.channels(),
+            self.current.sample_rate(),
+        ));
+        let outgoing = std::mem::replace(&mut self.current, incoming);
+
+        self.fade = Some(Fade {
+            outgoing,
+            gain: f32::from_bits(self.shared.gain.load(Ordering::Relaxed)),
+            position: 0,
+            length: length.max(1),
+        });
+        self.took_over(track.gain);
+    }
+}
+
+impl Source for Gapless {
+    /// The end of a song has to be the end of a frame as well, so the next one starts cleanly
+    fn current_frame_len(&self) -> Option<usize> {
+        if let Some(len) = self.current.current_frame_len() {
+            if len > 0 {
+                return Some(len);
+            }
+        }
+
+        match self.current.size_hint() {
+            (0, _) => Some(THRESHOLD),
+            (lower, _) => Some(lower),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.current.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.current.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Only seeks within the song that is playing
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.current.try_seek(pos)?;
+        self.fade = None;
+
+        let samples_per_second = self.shared.samples_per_second.load(Ordering::Relaxed);
+        self.played = frame_start(
+            (pos.as_secs_f64() * samples_per_second as f64) as u64,
+            self.current.channels() as u32,
+        );
+        self.shared.samples.store(self.played, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::Decoder;
+    use std::f32::consts::PI;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::Path;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn write_sine(path: &Path, frequency: f32, samples: usize) -> Vec<i16> {
+        write_wave(path, frequency, samples, 1)
+    }
+
+    fn write_wave(path: &Path, frequency: f32, samples: usize, channels: u16) -> Vec<i16> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+
+        // offset so the wave never sits at zero
+        let wave = (0..samples)
+            .map(|i| {
+                let t = (i / channels as usize) as f32 / SAMPLE_RATE as f32;
+                (((2.0 * PI * frequency * t).sin() * 0.5 + 0.6) * i16::MAX as f32 * 0.5) as i16
+            })
+            .collect::<Vec<i16>>();
+
+        for sample in &wave {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        wave
+    }
+
+    fn decode(path: &Path) -> Track {
+        Track {
+            source: Box::new(Decoder::new(BufReader::new(File::open(path).unwrap())).unwrap()),
+            gain: 1.0,
+        }
+    }
+
+    #[test]
+    fn no_silence_between_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.wav");
+        let second = dir.path().join("second.wav");
+
+        let mut expected = write_sine(&first, 440.0, SAMPLE_RATE as usize / 2);
+        expected.extend(write_sine(&second, 660.0, SAMPLE_RATE as usize / 2));
+
+        let (source, handle) = gapless(decode(&first));
+        assert!(handle.set_next(Some(decode(&second)), None, 0));
+
+        let played = source.collect::<Vec<i16>>();
+
+        assert_eq!(handle.started(), 1);
+        assert_eq!(played.len(), expected.len());
+        assert_eq!(played, expected);
+        assert!(played.iter().all(|sample| *sample != 0));
+    }
+
+    #[test]
+    fn next_song_can_be_replaced_until_it_starts() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.wav");
+        let second = dir.path().join("second.wav");
+        let third = dir.path().join("third.wav");
+
+        let mut expected = write_sine(&first, 440.0, 1000);
+        write_sine(&second, 550.0, 1000);
+        expected.extend(write_sine(&third, 660.0, 1000));
+
+        let (mut source, handle) = gapless(decode(&first));
+        assert!(handle.set_next(Some(decode(&second)), None, 0));
+        assert!(handle.set_next(Some(decode(&third)), None, 0));
+
+        let mut played = source.by_ref().take(1500).collect::<Vec<i16>>();
+        assert!(!handle.set_next(None, None, 0));
+
+        played.extend(source);
+        assert_eq!(played, expected);
+    }
+
+    #[test]
+    fn seeking_moves_the_position_and_the_crossfade() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.wav");
+        let second = dir.path().join("second.wav");
+
+        write_sine(&first, 440.0, SAMPLE_RATE as usize);
+        write_sine(&second, 660.0, SAMPLE_RATE as usize);
+
+        let (mut source, handle) = gapless(decode(&first));
+        let crossfade = Crossfade {
+            at: Duration::from_millis(500),
+            length: Duration::from_millis(100),
+        };
+        assert!(handle.set_next(Some(decode(&second)), Some(crossfade), 0));
+
+        source.try_seek(Duration::from_millis(250)).unwrap();
+        assert_eq!(handle.position(), Duration::from_millis(250));
+
+        source
+            .by_ref()
+            .take(SAMPLE_RATE as usize / 4)
+            .for_each(drop);
+        assert_eq!(handle.position(), Duration::from_millis(500));
+        assert_eq!(handle.started(), 0);
+
+        source.next();
+        assert_eq!(handle.started(), 1);
+    }
+
+    #[test]
+    fn crossfade_starts_on_a_frame() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.wav");
+        let second = dir.path().join("second.wav");
+
+        write_wave(&first, 440.0, 4000, 2);
+        write_wave(&second, 660.0, 4000, 2);
+
+        // lands on the right channel of frame 500
+        let at = Duration::from_secs_f64(1001.5 / (2 * SAMPLE_RATE) as f64);
+
+        let (mut source, handle) = gapless(decode(&first));
+        let crossfade = Crossfade {
+            at,
+            length: Duration::from_millis(10),
+        };
+        assert!(handle.set_next(Some(decode(&second)), Some(crossfade), 0));
+
+        source.by_ref().take(1000).for_each(drop);
+        assert_eq!(handle.started(), 0);
+
+        source.next();
+        assert_eq!(handle.started(), 1);
+    }
+}
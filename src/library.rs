@@ -0,0 +1,486 @@
+use crate::format::is_audio_file;
+use crate::scan::{self, Scan, ScanEvent};
+use crate::song::{Song, SongBuilder};
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// Bumped whenever `Song` changes, older indexes are thrown away and rebuilt
+const VERSION: u32 = 4;
+
+static LIBRARY: LazyLock<Arc<Mutex<Library>>> =
+    LazyLock::new(|| Arc::new(Mutex::new(Library::load())));
+
+/// The library shared by every part of the app, read from disk the first time it is used
+pub fn shared() -> Arc<Mutex<Library>> {
+    LIBRARY.clone()
+}
+
+/// Reads the song from the index, only parsing its tags when the file changed since
+pub fn song(path: &Path) -> Result<Song> {
+    let stamp = Stamp::read(path)?;
+
+    if let Some(song) = shared().lock().unwrap().get(path, &stamp) {
+        return Ok(song);
+    }
+
+    let song = SongBuilder::new().from_path(path)?.build();
+    shared().lock().unwrap().insert(song.clone(), stamp);
+
+    Ok(song)
+}
+
+/// The song when it is indexed and did not change since, never reads the file itself
+pub fn cached(path: &Path) -> Option<Song> {
+    let stamp = Stamp::read(path).ok()?;
+
+    shared().lock().unwrap().get(path, &stamp)
+}
+
+/// Indexes every song under `root` in the background. Only files that changed are read again,
+/// songs that were deleted are dropped from the index.
+pub fn scan(root: PathBuf) -> Scan {
+    scan::spawn(move |tx| rescan(&root, &shared(), library_path().as_deref(), tx))
+}
+
+fn rescan(root: &Path, library: &Mutex<Library>, index: Option<&Path>, tx: &Sender<ScanEvent>) {
+    if !root.is_dir() {
+        let _ = tx.send(ScanEvent::ERROR(format!(
+            "{}: music directory does not exist",
+            root.display()
+        )));
+        return;
+    }
+
+    let paths = WalkDir::new(root)
+        .follow_links(true)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| is_audio_file(path))
+        .collect::<Vec<PathBuf>>();
+
+    let _ = tx.send(ScanEvent::STARTED(paths.len()));
+
+    for path in paths {
+        if let Ok(stamp) = Stamp::read(&path) {
+            let indexed = library.lock().unwrap().get(&path, &stamp).is_some();
+
+            // tags are read without holding the lock, so the UI never waits on them
+            if !indexed {
+                if let Ok(builder) = SongBuilder::new().from_path(&path) {
+                    library.lock().unwrap().insert(builder.build(), stamp);
+                }
+            }
+        }
+
+        let _ = tx.send(ScanEvent::SCANNED(path));
+    }
+
+    // files are checked without the lock, that can take a while on a network share
+    let indexed = library.lock().unwrap().paths();
+    let gone = indexed
+        .into_iter()
+        .filter(|path| !path.exists())
+        .collect::<Vec<PathBuf>>();
+
+    let mut locked = library.lock().unwrap();
+    locked.remove(&gone);
+
+    let index = match index {
+        Some(index) => index,
+        None => return,
+    };
+
+    // only encoding holds the lock, the disk is written to without it
+    let encoded = locked.take_changes();
+    drop(locked);
+
+    let saved = encoded.and_then(|data| data.map_or(Ok(()), |data| write(index, &data)));
+
+    if let Err(e) = saved {
+        library.lock().unwrap().changed = true;
+        let _ = tx.send(ScanEvent::ERROR(format!("Saving the library: {}", e)));
+    }
+}
+
+pub fn save() -> Result<()> {
+    shared().lock().unwrap().save()
+}
+
+/// Tells if a file changed without reading it
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    modified: SystemTime,
+    size: u64,
+}
+
+impl Stamp {
+    pub fn read(path: &Path) -> Result<Stamp> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Stamp {
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    stamp: Stamp,
+    song: Song,
+}
+
+/// Metadata of every song seen so far, keyed by path
+#[derive(Default)]
+pub struct Library {
+    entries: HashMap<PathBuf, Entry>,
+    changed: bool,
+}
+
+fn library_path() -> Option<PathBuf> {
+    // tests never touch the real index
+    if cfg!(test) {
+        return None;
+    }
+
+    dirs::data_dir().map(|dir| dir.join("rust-music-player").join("library.bin"))
+}
+
+/// A crash halfway through leaves the old index in place, only a complete one replaces it
+fn write(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let temporary = path.with_extension("bin.tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(temporary, path)?;
+
+    Ok(())
+}
+
+impl Library {
+    pub fn load() -> Library {
+        library_path()
+            .map(|path| Library::load_from(&path))
+            .unwrap_or_default()
+    }
+
+    /// A missing, broken or outdated index starts the library from scratch
+    pub fn load_from(path: &Path) -> Library {
+        Library::read(path).unwrap_or_default()
+    }
+
+    fn read(path: &Path) -> Option<Library> {
+        let mut reader = BufReader::new(File::open(path).ok()?);
+
+        let version: u32 = bincode::deserialize_from(&mut reader).ok()?;
+        if version != VERSION {
+            return None;
+        }
+
+        let entries = bincode::deserialize_from(&mut reader).ok()?;
+
+        Some(Library {
+            entries,
+            changed: false,
+        })
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        match library_path() {
+            Some(path) => self.save_to(&path),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the index when anything changed since it was read
+    pub fn save_to(&mut self, path: &Path) -> Result<()> {
+        if let Some(data) = self.take_changes()? {
+            if let Err(e) = write(path, &data) {
+                self.changed = true;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The encoded index when anything changed, from then on it counts as saved
+    fn take_changes(&mut self) -> Result<Option<Vec<u8>>> {
+        if !self.changed {
+            return Ok(None);
+        }
+
+        let mut data = bincode::serialize(&VERSION)?;
+        bincode::serialize_into(&mut data, &self.entries)?;
+        self.changed = false;
+
+        Ok(Some(data))
+    }
+
+    /// The song at `path`, unless the file changed since it was indexed
+    pub fn get(&self, path: &Path, stamp: &Stamp) -> Option<Song> {
+        self.entries
+            .get(path)
+            .filter(|entry| entry.stamp == *stamp)
+            .map(|entry| entry.song.clone())
+    }
+
+    pub fn songs(&self) -> impl Iterator<Item = &Song> {
+        self.entries.values().map(|entry| &entry.song)
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.entries.keys().cloned().collect()
+    }
+
+    /// Forgets the songs at `paths`
+    pub fn remove(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            if self.entries.remove(path).is_some() {
+                self.changed = true;
+            }
+        }
+    }
+
+    pub fn insert(&mut self, song: Song, stamp: Stamp) {
+        self.entries.insert(song.get_path(), Entry { stamp, song });
+        self.changed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn song(path: &str) -> Song {
+        SongBuilder::new()
+            .title(String::from(path))
+            .path(PathBuf::from(path))
+            .build()
+    }
+
+    fn stamp(size: u64) -> Stamp {
+        Stamp {
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            size,
+        }
+    }
+
+    #[test]
+    fn saved_songs_load_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("data").join("library.bin");
+
+        let mut library = Library::default();
+        library.insert(song("/music/a.flac"), stamp(1));
+        library.insert(song("/music/b.flac"), stamp(2));
+        library.save_to(&index).unwrap();
+
+        // the index is written next to itself and moved over, nothing is left behind
+        assert!(index.exists());
+        assert!(!index.with_extension("bin.tmp").exists());
+
+        let loaded = Library::load_from(&index);
+        let mut paths = loaded.paths();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/music/a.flac"),
+                PathBuf::from("/music/b.flac")
+            ]
+        );
+        assert_eq!(
+            loaded.get(Path::new("/music/a.flac"), &stamp(1)),
+            Some(song("/music/a.flac"))
+        );
+        assert!(!loaded.changed);
+    }
+
+    #[test]
+    fn saving_replaces_the_index_only_when_something_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("library.bin");
+
+        let mut library = Library::default();
+        library.insert(song("/music/a.flac"), stamp(1));
+        library.save_to(&index).unwrap();
+
+        fs::write(&index, b"left alone").unwrap();
+        library.save_to(&index).unwrap();
+        assert_eq!(fs::read(&index).unwrap(), b"left alone");
+
+        library.remove(&[PathBuf::from("/music/a.flac")]);
+        library.save_to(&index).unwrap();
+        assert!(Library::load_from(&index).paths().is_empty());
+        assert!(!index.with_extension("bin.tmp").exists());
+    }
+
+    #[test]
+    fn other_versions_are_thrown_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("library.bin");
+
+        let mut entries = HashMap::new();
+        entries.insert(
+            PathBuf::from("/music/a.flac"),
+            Entry {
+                stamp: stamp(1),
+                song: song("/music/a.flac"),
+            },
+        );
+
+        let mut data = bincode::serialize(&(VERSION - 1)).unwrap();
+        data.extend(bincode::serialize(&entries).unwrap());
+        fs::write(&index, data).unwrap();
+        assert!(Library::load_from(&index).paths().is_empty());
+
+        let mut data = bincode::serialize(&VERSION).unwrap();
+        data.extend(bincode::serialize(&entries).unwrap());
+        fs::write(&index, data).unwrap();
+        assert_eq!(Library::load_from(&index).paths().len(), 1);
+
+        fs::write(&index, b"garbage").unwrap();
+        assert!(Library::load_from(&index).paths().is_empty());
+        assert!(Library::load_from(&dir.path().join("missing.bin"))
+            .paths()
+            .is_empty());
+    }
+
+    #[test]
+    fn changed_files_are_not_served_from_the_index() {
+        let mut library = Library::default();
+        let path = Path::new("/music/a.flac");
+        library.insert(song("/music/a.flac"), stamp(1));
+
+        assert!(library.get(path, &stamp(1)).is_some());
+        assert!(library.get(path, &stamp(2)).is_none());
+
+        let touched = Stamp {
+            modified: stamp(1).modified + Duration::from_secs(1),
+            ..stamp(1)
+        };
+        assert!(library.get(path, &touched).is_none());
+    }
+
+    #[test]
+    fn stamps_follow_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.flac");
+
+        fs::write(&path, b"1234").unwrap();
+        let before = Stamp::read(&path).unwrap();
+        assert_eq!(before.size, 4);
+        assert!(Stamp::read(&path).unwrap() == before);
+
+        fs::write(&path, b"123456").unwrap();
+        assert!(Stamp::read(&path).unwrap() != before);
+        assert!(Stamp::read(&dir.path().join("missing.flac")).is_err());
+    }
+
+    fn wav(path: &Path, samples: usize) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..samples {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    /// A music folder with two songs, and where its index goes
+    fn music() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("music");
+        fs::create_dir(&root).unwrap();
+        wav(&root.join("a.wav"), 800);
+        wav(&root.join("b.wav"), 800);
+
+        let index = dir.path().join("library.bin");
+        (dir, root, index)
+    }
+
+    fn sorted(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn rescans_only_read_files_that_changed() {
+        let (_dir, root, index) = music();
+        let (a, b) = (root.join("a.wav"), root.join("b.wav"));
+        let library = Mutex::new(Library::default());
+        let (tx, _rx) = mpsc::channel();
+
+        rescan(&root, &library, Some(&index), &tx);
+        assert_eq!(
+            sorted(library.lock().unwrap().paths()),
+            vec![a.clone(), b.clone()]
+        );
+        assert_eq!(Library::load_from(&index).paths().len(), 2);
+
+        // a song that is read again loses the title it is given here
+        for path in [&a, &b] {
+            let cached = SongBuilder::new()
+                .title(String::from("cached"))
+                .path(path.clone())
+                .build();
+            library
+                .lock()
+                .unwrap()
+                .insert(cached, Stamp::read(path).unwrap());
+        }
+        wav(&b, 1600);
+
+        rescan(&root, &library, Some(&index), &tx);
+
+        let library = library.lock().unwrap();
+        let title = |path: &Path| {
+            library
+                .get(path, &Stamp::read(path).unwrap())
+                .unwrap()
+                .title()
+        };
+        assert_eq!(title(&a), "cached");
+        assert_eq!(title(&b), "b");
+        assert!(!library.changed);
+    }
+
+    #[test]
+    fn rescans_drop_deleted_files() {
+        let (_dir, root, index) = music();
+        let library = Mutex::new(Library::default());
+        let (tx, _rx) = mpsc::channel();
+
+        rescan(&root, &library, Some(&index), &tx);
+        fs::remove_file(root.join("a.wav")).unwrap();
+        rescan(&root, &library, Some(&index), &tx);
+
+        assert_eq!(library.lock().unwrap().paths(), vec![root.join("b.wav")]);
+        assert_eq!(Library::load_from(&index).paths(), vec![root.join("b.wav")]);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::format::is_audio_file;
+use crate::replaygain::ReplayGain;
+use crate::scan::{self, Scan, ScanEvent};
+use color_eyre::eyre::{eyre, Result};
+use rodio::{Decoder, Source};
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// ReplayGain 2.0 plays everything at -18 LUFS
+const REFERENCE: f64 = -18.0;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const OVERSAMPLING: usize = 4;
+const TAPS: usize = 12;
+
+/// Integrated loudness as the mean square of every 400ms block, so albums can be gated as a whole
+pub struct Loudness {
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl Loudness {
+    /// Integrated loudness in LUFS as defined by ITU-R BS.1770, None for silence
+    pub fn integrated(&self) -> Option<f64> {
+        integrated(self.blocks.iter())
+    }
+
+    pub fn true_peak(&self) -> f64 {
+        self.peak
+    }
+
+    pub fn album<'a>(tracks: impl Iterator<Item = &'a Loudness> + Clone) -> (Option<f64>, f64) {
+        let loudness = integrated(tracks.clone().flat_map(|track| track.blocks.iter()));
+        let peak = tracks.map(|track| track.peak).fold(0.0, f64::max);
+
+        (loudness, peak)
+    }
+}
+
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn integrated<'a>(blocks: impl Iterator<Item = &'a f64> + Clone) -> Option<f64> {
+    let mean = |threshold: f64| {
+        let gated = blocks
+            .clone()
+            .filter(|power| block_loudness(**power) > threshold)
+            .collect::<Vec<&f64>>();
+
+        match gated.len() {
+            0 => None,
+            len => Some(gated.into_iter().sum::<f64>() / len as f64),
+        }
+    };
+
+    let relative = block_loudness(mean(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+
+    Some(block_loudness(mean(relative.max(ABSOLUTE_GATE))?))
+}
+
+/// Second order IIR filter
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[1] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+/// The two stages of K-weighting, a high shelf for the head and a high pass
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Surround channels count more, the LFE channel not at all
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6.., 3) => 0.0,
+        (6.., 4) | (6.., 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Windowed sinc interpolation filter, split into one phase per oversampled position
+fn interpolation_filter() -> Vec<[f64; TAPS]> {
+    let len = OVERSAMPLING * TAPS;
+    let center = (len - 1) as f64 / 2.0;
+
+    (0..OVERSAMPLING)
+        .map(|phase| {
+            let mut taps = [0.0; TAPS];
+
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let i = (k * OVERSAMPLING + phase) as f64;
+                let x = (i - center) / OVERSAMPLING as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = 0.5 - 0.5 * (2.0 * PI * (i + 0.5) / len as f64).cos();
+
+                *tap = sinc * window;
+            }
+
+            let sum = taps.iter().sum::<f64>();
+            taps.map(|tap| tap / sum)
+        })
+        .collect()
+}
+
+/// Decodes the whole file, measuring loudness and the true peak by oversampling
+pub fn measure(path: &Path) -> Result<Loudness> {
+    let source = Decoder::new(BufReader::new(File::open(path)?))?;
+
+    let channels = source.channels() as usize;
+    let rate = source.sample_rate() as usize;
+
+    if channels == 0 || rate == 0 {
+        return Err(eyre!("No audio"));
+    }
+
+    let mut filters = vec![k_weighting(rate as f64); channels];
+    let phases = interpolation_filter();
+    let mut history = vec![[0.0; TAPS]; channels];
+
+    // blocks are 400ms long and start every 100ms
+    let step = rate / 10;
+    let mut segments: Vec<f64> = Vec::new();
+    let mut segment = 0.0;
+    let mut frames = 0;
+
+    let mut peak: f64 = 0.0;
+    let mut channel = 0;
+
+    for sample in source {
+        let x = sample as f64 / 32768.0;
+
+        let [shelf, high_pass] = &mut filters[channel];
+        let weighted = high_pass.process(shelf.process(x));
+        segment += channel_weight(channel, channels) * weighted * weighted;
+
+        let history = &mut history[channel];
+        history.rotate_right(1);
+        history[0] = x;
+
+        for taps in &phases {
+            let y = taps
+                .iter()
+                .zip(history.iter())
+                .map(|(h, x)| h * x)
+                .sum::<f64>();
+            peak = peak.max(y.abs());
+        }
+        peak = peak.max(x.abs());
+
+        channel += 1;
+        if channel == channels {
+            channel = 0;
+            frames += 1;
+
+            if frames == step {
+                segments.push(segment);
+                segment = 0.0;
+                frames = 0;
+            }
+        }
+    }
+
+    let blocks = segments
+        .windows(4)
+        .map(|window| window.iter().sum::<f64>() / (step * 4) as f64)
+        .collect();
+
+    Ok(Loudness { blocks, peak })
+}
+
+fn gain(loudness: Option<f64>) -> Option<f32> {
+    loudness.map(|loudness| (REFERENCE - loudness) as f32)
+}
+
+/// Measures every song in `dir` as one album and writes the tags back, in the background
+pub fn scan_directory(dir: PathBuf) -> Scan {
+    scan::spawn(move |tx| {
+        let mut paths = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| is_audio_file(path))
+                .collect::<Vec<PathBuf>>(),
+            Err(e) => {
+                let _ = tx.send(ScanEvent::ERROR(format!("{}: {}", dir.display(), e)));
+                return;
+            }
+        };
+        paths.sort();
+
+        let _ = tx.send(ScanEvent::STARTED(paths.len()));
+
+        let mut tracks = Vec::new();
+
+        for path in paths {
+            match measure(&path) {
+                Ok(loudness) => tracks.push((path.clone(), loudness)),
+                Err(e) => {
+                    let _ = tx.send(ScanEvent::ERROR(format!("{}: {}", path.display(), e)));
+                }
+            }
+
+            let _ = tx.send(ScanEvent::SCANNED(path));
+        }
+
+        let (album_loudness, album_peak) = Loudness::album(tracks.iter().map(|(_, track)| track));
+
+        for (path, track) in &tracks {
+            let replay_gain = ReplayGain {
+                track_gain: gain(track.integrated()),
+                track_peak: Some(track.true_peak() as f32),
+                album_gain: gain(album_loudness),
+                album_peak: Some(album_peak as f32),
+            };
+
+            if let Err(e) = replay_gain.write(path) {
+                let _ = tx.send(ScanEvent::ERROR(format!("{}: {}", path.display(), e)));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_4;
+
+    const RATE: u32 = 48000;
+
+    /// Stereo wave with both channels alike, `amplitude` in dBFS for every second of it
+    fn write_wave(path: &Path, frequency: f64, phase: f64, amplitude: &[f64]) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+
+        for i in 0..amplitude.len() * RATE as usize {
+            let gain = 10f64.powf(amplitude[i / RATE as usize] / 20.0);
+            let t = i as f64 / RATE as f64;
+            let sample = ((2.0 * PI * frequency * t + phase).sin() * gain * 32767.0) as i16;
+
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+
+        writer.finalize().unwrap();
+    }
+
+    fn measured(amplitude: &[f64]) -> Loudness {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wave.wav");
+        write_wave(&path, 1000.0, 0.0, amplitude);
+
+        measure(&path).unwrap()
+    }
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance, "{} is not {}", a, b);
+    }
+
+    /// EBU Tech 3341 case 1, a 1 kHz sine at -23 dBFS measures -23 LUFS
+    #[test]
+    fn sine_at_reference_level() {
+        let loudness = measured(&[-23.0; 5]);
+
+        assert_close(loudness.integrated().unwrap(), -23.0, 0.1);
+        assert_close(gain(loudness.integrated()).unwrap() as f64, 5.0, 0.1);
+    }
+
+    /// EBU Tech 3341 case 3, shortened. The quiet parts fall under the relative gate,
+    /// only the blocks overlapping both parts count a little.
+    #[test]
+    fn relative_gate() {
+        let mut amplitude = vec![-36.0; 2];
+        amplitude.extend([-23.0; 20]);
+        amplitude.extend([-36.0; 2]);
+
+        assert_close(measured(&amplitude).integrated().unwrap(), -23.0, 0.1);
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        let mut amplitude = vec![-23.0; 10];
+        amplitude.extend([-200.0; 2]);
+
+        assert_close(measured(&amplitude).integrated().unwrap(), -23.0, 0.1);
+        assert_eq!(measured(&[-200.0; 2]).integrated(), None);
+    }
+
+    #[test]
+    fn album_is_gated_as_a_whole() {
+        let loud = measured(&[-20.0; 2]);
+        let quiet = measured(&[-26.0; 2]);
+
+        let (album, peak) = Loudness::album([&loud, &quiet].into_iter());
+        let album = album.unwrap();
+
+        assert!(album > quiet.integrated().unwrap() && album < loud.integrated().unwrap());
+        assert_eq!(peak, loud.true_peak());
+    }
+
+    /// A quarter of the sample rate at 45 degrees never samples its peak, only oversampling finds it
+    #[test]
+    fn true_peak_between_samples() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wave.wav");
+        write_wave(&path, RATE as f64 / 4.0, FRAC_PI_4, &[-6.0; 1]);
+
+        let expected = 10f64.powf(-6.0 / 20.0);
+        let peak = measure(&path).unwrap().true_peak();
+
+        assert_close(peak, expected, 0.02);
+        assert!(peak > expected * FRAC_PI_4.sin() + 0.1);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::playlist::{relative_path, resolve_path, PlaylistEntry};
+use crate::song::Song;
+use color_eyre::eyre::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Reads an extended M3U playlist, plain M3U files without `#EXTINF` lines work as well
+pub fn read(path: &Path) -> Result<Vec<PlaylistEntry>> {
+    let bytes = fs::read(path)?;
+    let contents = String::from_utf8_lossy(&bytes);
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut entries = Vec::new();
+    let mut extinf: Option<PlaylistEntry> = None;
+
+    for line in contents.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            extinf = Some(parse_extinf(info));
+            continue;
+        }
+
+        if let Some(artist) = line.strip_prefix("#EXTART:") {
+            extinf
+                .get_or_insert_with(|| PlaylistEntry::new(PathBuf::new()))
+                .artist = Some(artist.trim().to_string());
+            continue;
+        }
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let mut entry = match extinf.take() {
+            Some(info) => PlaylistEntry {
+                path: resolve_path(dir, line),
+                ..info
+            },
+            None => PlaylistEntry::new(resolve_path(dir, line)),
+        };
+
+        entry.strip_artist();
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Parses `#EXTINF:<seconds>,<title>`, the duration is -1 when it is unknown
+fn parse_extinf(info: &str) -> PlaylistEntry {
+    let mut entry = PlaylistEntry::new(PathBuf::new());
+
+    let (duration, name) = match info.split_once(',') {
+        Some((duration, name)) => (duration, name.trim()),
+        None => (info, ""),
+    };
+
+    // attributes like tvg-id="..." can follow the duration
+    entry.duration = duration
+        .split_whitespace()
+        .next()
+        .and_then(|d| d.parse::<f64>().ok())
+        .filter(|d| *d >= 0.0)
+        .map(|d| d.round() as u32);
+
+    if !name.is_empty() {
+        entry.title = Some(name.to_string());
+    }
+
+    entry
+}
+
+pub fn write(path: &Path, songs: &[Song]) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut contents = String::from("#EXTM3U\n");
+
+    for song in songs {
+        let duration = match song.duration() {
+            Some(duration) => duration as i64,
+            None => -1,
+        };
+
+        let artist = song.artist().filter(|artist| !artist.is_empty());
+
+        let name = match &artist {
+            Some(artist) => format!("{} - {}", artist, song.title()),
+            None => song.title(),
+        };
+
+        contents.push_str(&format!("#EXTINF:{},{}\n", duration, name));
+
+        if let Some(artist) = artist {
+            contents.push_str(&format!("#EXTART:{}\n", artist));
+        }
+        contents.push_str(&format!(
+            "{}\n",
+            relative_path(dir, &song.get_path()).display()
+        ));
+    }
+
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::tests::fixture;
+    use crate::song::SongBuilder;
+
+    #[test]
+    fn reads_fixture() {
+        let entries = read(&fixture("playlist.m3u")).unwrap();
+        let dir = fixture("playlist.m3u").parent().unwrap().to_path_buf();
+
+        assert_eq!(entries.len(), 4);
+
+        assert_eq!(
+            entries[0],
+            PlaylistEntry {
+                path: dir.join(
+                    "Boards of Canada/Music Has the Right to Children/01 Wildlife Analysis.mp3"
+                ),
+                title: Some(String::from("Wildlife Analysis")),
+                artist: Some(String::from("Boards of Canada")),
+                album: None,
+                duration: Some(77),
+            }
+        );
+
+        assert_eq!(
+            entries[1].path,
+            PathBuf::from("/music/Sigur Rós/Takk.../02 Glósóli.flac")
+        );
+        // without #EXTART the title is kept whole
+        assert_eq!(entries[1].title.as_deref(), Some("Sigur Rós - Glósóli"));
+        assert_eq!(entries[1].artist, None);
+        assert_eq!(entries[1].duration, Some(375));
+
+        assert_eq!(entries[2], PlaylistEntry::new(dir.join("Untitled.ogg")));
+
+        assert_eq!(entries[3].path, dir.join("../Elsewhere/Roygbiv.mp3"));
+        assert_eq!(entries[3].title.as_deref(), Some("Roygbiv"));
+        assert_eq!(entries[3].artist, None);
+        assert_eq!(entries[3].duration, None);
+    }
+
+    #[test]
+    fn parses_extinf() {
+        let entry = parse_extinf("123,Intro - Live");
+        assert_eq!(entry.duration, Some(123));
+        assert_eq!(entry.artist, None);
+        assert_eq!(entry.title.as_deref(), Some("Intro - Live"));
+
+        let entry = parse_extinf("-1,");
+        assert_eq!(entry.duration, None);
+        assert_eq!(entry.title, None);
+
+        let entry = parse_extinf("nonsense");
+        assert_eq!(entry.duration, None);
+        assert_eq!(entry.artist, None);
+    }
+
+    #[test]
+    fn writes_extinf_lines_and_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("playlist.m3u8");
+
+        let songs = vec![
+            SongBuilder::new()
+                .title(String::from("Wildlife Analysis"))
+                .artist(Some(String::from("Boards of Canada")))
+                .duration(Some(77))
+                .path(dir.path().join("Boards of Canada/01 Wildlife Analysis.mp3"))
+                .build(),
+            SongBuilder::new()
+                .title(String::from("Intro - Live"))
+                .artist(None)
+                .duration(None)
+                .path(PathBuf::from("/music/Sigur Rós/02 Glósóli.flac"))
+                .build(),
+        ];
+
+        write(&path, &songs).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            contents,
+            "#EXTM3U\n\
+             #EXTINF:77,Boards of Canada - Wildlife Analysis\n\
+             #EXTART:Boards of Canada\n\
+             Boards of Canada/01 Wildlife Analysis.mp3\n\
+             #EXTINF:-1,Intro - Live\n\
+             /music/Sigur Rós/02 Glósóli.flac\n"
+        );
+    }
+}
@@ -1,17 +1,57 @@
 mod browser_list;
+mod config;
+mod format;
+mod gapless;
+mod library;
+mod loudness;
+mod m3u;
+mod opus;
+mod player;
 mod playlist;
+mod pls;
+mod popup;
+mod queue;
+mod replaygain;
+mod scan;
+mod screen_library;
+mod screen_playlists;
+mod screen_queue;
 mod screen_welcome;
+mod search;
 mod song;
+mod status_bar;
+mod xspf;
 
 use browser_list::{BrowserState, BrowserStateBuilder, FileType};
-use color_eyre::eyre::{Report, Result};
-use event::KeyCode;
-use rodio::Sink;
-use rodio::{Decoder, OutputStream};
+use color_eyre::eyre::{self, eyre, EyreHandler, Report, Result};
+use config::{Config, State};
+use event::{KeyCode, KeyEvent, KeyModifiers};
+use format::is_audio_file;
+use player::{
+    init_player_thread, parse_timestamp, PlayerEvent, PlayerState, Seek, ThreadCommand,
+    ThreadMessage, Volume,
+};
+use playlist::PlaylistEntry;
+use popup::{Popup, PopupKind};
+use queue::RepeatMode;
+use scan::Scan;
+use screen_library::{screen_library, LibraryState};
+use screen_playlists::{screen_playlists, PlaylistsState};
+use screen_queue::{screen_queue, QueueState};
 use screen_welcome::screen_welcome;
-use std::{fs::File, io, io::BufReader, path::PathBuf, sync::mpsc, sync::mpsc::Sender, thread};
-// use playlist::PlaylistBuilder;
-// use song::Song;
+use search::Search;
+use song::Song;
+use std::{
+    error::Error,
+    fmt, io,
+    panic::{self, Location},
+    path::PathBuf,
+    process,
+    sync::mpsc,
+    sync::mpsc::{Receiver, Sender},
+    thread,
+    time::Duration,
+};
 
 // ratatui
 use crossterm::{
@@ -22,55 +62,49 @@ use crossterm::{
 #[allow(unused_imports)]
 use ratatui::{prelude::*, widgets::*};
 
-#[allow(dead_code)]
 #[derive(PartialEq)]
 enum Screen {
     WELCOME,
     QUEUE,
     PLAYLISTS,
     BROWSER,
-}
-
-#[derive(PartialEq)]
-enum PlayerState {
-    PLAYING,
-    PAUSED,
-}
-
-enum ThreadCommand {
-    NONE,
-    SONG,
-    PLAYPAUSE,
-    END,
-    SKIP,
+    LIBRARY,
 }
 
 struct App {
     running: bool,
     browser_state: BrowserState,
+    queue_state: QueueState,
+    playlists_state: PlaylistsState,
+    library_state: LibraryState,
     screen: Screen,
+    popup: Option<Popup>,
+    marked: Vec<PathBuf>,
+    state: State,
+    player_state: PlayerState,
+    position: Duration,
+    shuffle: bool,
+    repeat: RepeatMode,
+    error: Option<String>,
+    music_dir: PathBuf,
+    library_scan: Option<Scan>,
+    loudness_scan: Option<Scan>,
     tx: Sender<ThreadMessage>,
-}
-
-struct ThreadMessage {
-    command: ThreadCommand,
-    msg: Option<String>,
+    rx: Receiver<PlayerEvent>,
 }
 
 impl App {
-    pub fn play_song(&self) -> Result<()> {
+    fn current_song_path(&self) -> Option<String> {
         let file = self
             .browser_state
             .get_current_file()
             .unwrap_or(String::from(""));
 
-        let extensions = vec![".mp3", ".flac"];
-
-        if file.is_empty() || !extensions.iter().any(|suffix| file.ends_with(suffix)) {
-            return Ok(());
+        if file.is_empty() || !is_audio_file(&self.browser_state.get_path().join(&file)) {
+            return None;
         }
 
-        let path = String::from(format!(
+        Some(String::from(format!(
             "{}/{}",
             self.browser_state
                 .get_path()
@@ -79,15 +113,363 @@ impl App {
                 .ok()
                 .unwrap(),
             file
-        ));
+        )))
+    }
 
-        self.tx.send(ThreadMessage {
-            command: ThreadCommand::SONG,
-            msg: Some(path),
-        })?;
+    pub fn play_song(&self) -> Result<()> {
+        if let Some(path) = self.current_song_path() {
+            self.tx.send(ThreadMessage {
+                command: ThreadCommand::SONG,
+                msg: Some(path),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn play_song_next(&self) -> Result<()> {
+        if let Some(path) = self.current_song_path() {
+            self.tx.send(ThreadMessage {
+                command: ThreadCommand::PLAYNEXT,
+                msg: Some(path),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn enqueue(&self, songs: Vec<Song>) -> Result<()> {
+        for song in songs {
+            self.tx.send(ThreadMessage {
+                command: ThreadCommand::SONG,
+                msg: song.get_path().to_str().map(String::from),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens the selected group or album, in the tracks view it adds the song
+    pub fn open_library(&mut self) -> Result<()> {
+        if !self.library_state.enter() {
+            self.enqueue(self.library_state.selected_songs())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn play_playlist(&self) -> Result<()> {
+        let playlist = match self.playlists_state.selected_playlist() {
+            Some(playlist) => playlist,
+            None => return Ok(()),
+        };
+
+        for song in playlist.songs() {
+            self.tx.send(ThreadMessage {
+                command: ThreadCommand::SONG,
+                msg: song.get_path().to_str().map(String::from),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn selected_song(&self) -> Option<PathBuf> {
+        match self.screen {
+            Screen::BROWSER => self.current_song_path().map(PathBuf::from),
+            Screen::QUEUE => self.queue_state.selected_song().map(|song| song.get_path()),
+            Screen::LIBRARY => self
+                .library_state
+                .selected_song()
+                .map(|song| song.get_path()),
+            _ => None,
+        }
+    }
+
+    pub fn toggle_mark(&mut self) {
+        if let Some(path) = self.selected_song() {
+            match self.marked.iter().position(|marked| *marked == path) {
+                Some(i) => {
+                    self.marked.remove(i);
+                }
+                None => self.marked.push(path),
+            }
+        }
+
+        match self.screen {
+            Screen::BROWSER => self.browser_state.next(),
+            Screen::QUEUE => self.queue_state.next(),
+            Screen::LIBRARY => self.library_state.next(),
+            _ => {}
+        }
+    }
+
+    /// The marked songs, or the selected one when nothing is marked
+    fn songs_to_add(&self) -> Vec<Song> {
+        let paths = if self.marked.is_empty() {
+            self.selected_song().into_iter().collect()
+        } else {
+            self.marked.clone()
+        };
+
+        paths
+            .into_iter()
+            .map(|path| match self.queue_state.song(&path) {
+                Some(song) => song.clone(),
+                None => PlaylistEntry::new(path).into_song(),
+            })
+            .collect()
+    }
+
+    pub fn add_to_playlist(&mut self) -> Result<()> {
+        if self.marked.is_empty() && self.selected_song().is_none() {
+            return Ok(());
+        }
+
+        self.playlists_state.update_state()?;
+        self.popup = Some(Popup::new(PopupKind::ADDTOPLAYLIST));
+
+        Ok(())
+    }
+
+    pub fn rename_playlist(&mut self) {
+        if let Some(playlist) = self.playlists_state.selected_playlist() {
+            if playlist.file().is_some() {
+                self.popup = Some(
+                    Popup::new(PopupKind::RENAMEPLAYLIST).with_input(playlist.name().to_string()),
+                );
+            }
+        }
+    }
+
+    fn confirm_popup(&mut self) -> Result<()> {
+        let popup = match &self.popup {
+            Some(popup) => popup,
+            None => return Ok(()),
+        };
+
+        match popup.kind {
+            PopupKind::ADDTOPLAYLIST => match popup.state.selected() {
+                Some(0) | None => {
+                    self.popup = Some(Popup::new(PopupKind::NEWPLAYLIST));
+                    return Ok(());
+                }
+                Some(i) => {
+                    let index = self.playlists_state.editable()[i - 1];
+                    self.playlists_state.add_songs(index, self.songs_to_add())?;
+                    self.marked.clear();
+                }
+            },
+            PopupKind::NEWPLAYLIST => {
+                let name = popup.input.trim().to_string();
+                self.playlists_state.create(&name, self.songs_to_add())?;
+                self.marked.clear();
+            }
+            PopupKind::RENAMEPLAYLIST => {
+                let name = popup.input.trim().to_string();
+                self.playlists_state.rename_selected(&name)?;
+            }
+            PopupKind::SEEK => match parse_timestamp(&popup.input) {
+                Some(to) => self.send_command(ThreadCommand::SEEK(Seek::TO(to)))?,
+                None => return Err(eyre!("Invalid time, use mm:ss")),
+            },
+            PopupKind::SEARCH => match self.searched_song() {
+                Some(song) => self.enqueue(vec![song])?,
+                None => return Ok(()),
+            },
+        }
+
+        self.popup = None;
+
+        Ok(())
+    }
+
+    /// The song selected in the search results
+    fn searched_song(&self) -> Option<Song> {
+        let popup = self.popup.as_ref()?;
+
+        popup
+            .search
+            .as_ref()?
+            .song(popup.state.selected()?)
+            .cloned()
+    }
+
+    fn search_key(&mut self, key: KeyEvent) -> Result<()> {
+        let popup = match self.popup.as_mut() {
+            Some(popup) => popup,
+            None => return Ok(()),
+        };
+
+        let len = popup.search.as_ref().map_or(0, |search| search.len());
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            Char('p') if control => {
+                if let Some(song) = self.searched_song() {
+                    self.tx.send(ThreadMessage {
+                        command: ThreadCommand::PLAYNEXT,
+                        msg: song.get_path().to_str().map(String::from),
+                    })?;
+                    self.popup = None;
+                }
+            }
+            Char('o') if control => {
+                if let Some(song) = self.searched_song() {
+                    self.browser_state.go_to(&song.get_path())?;
+                    self.screen = Screen::BROWSER;
+                    self.popup = None;
+                }
+            }
+            Char('n') if control => popup.next(len),
+            KeyCode::Down | KeyCode::Tab => popup.next(len),
+            KeyCode::Up | KeyCode::BackTab => popup.previous(len),
+            Char(c) if !control => {
+                popup.input.push(c);
+                popup.update_search();
+            }
+            KeyCode::Backspace => {
+                popup.input.pop();
+                popup.update_search();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn popup_key(&mut self, key: KeyEvent) {
+        let code = key.code;
+        let len = self.playlists_state.editable().len() + 1;
+
+        let popup = match self.popup.as_mut() {
+            Some(popup) => popup,
+            None => return,
+        };
+
+        match (&popup.kind, code) {
+            (_, KeyCode::Esc) => self.popup = None,
+            (_, KeyCode::Enter) => {
+                if let Err(e) = self.confirm_popup() {
+                    if let Some(popup) = self.popup.as_mut() {
+                        popup.error = Some(e.to_string());
+                    }
+                }
+            }
+            (PopupKind::SEARCH, _) => {
+                if let Err(e) = self.search_key(key) {
+                    if let Some(popup) = self.popup.as_mut() {
+                        popup.error = Some(e.to_string());
+                    }
+                }
+            }
+            (PopupKind::ADDTOPLAYLIST, Char('j')) | (PopupKind::ADDTOPLAYLIST, KeyCode::Down) => {
+                popup.next(len)
+            }
+            (PopupKind::ADDTOPLAYLIST, Char('k')) | (PopupKind::ADDTOPLAYLIST, KeyCode::Up) => {
+                popup.previous(len)
+            }
+            (PopupKind::ADDTOPLAYLIST, _) => {}
+            (_, Char(c)) => popup.input.push(c),
+            (_, KeyCode::Backspace) => {
+                popup.input.pop();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn send_command(&self, command: ThreadCommand) -> Result<()> {
+        self.tx.send(ThreadMessage { command, msg: None })?;
 
         Ok(())
     }
+
+    /// Scans the selected directory, or the one being browsed when a file is selected
+    pub fn scan_loudness(&mut self) {
+        if self.loudness_scan.is_some() {
+            self.error = Some(String::from("A loudness scan is already running"));
+            return;
+        }
+
+        let dir = self.browser_state.selected_dir();
+        self.loudness_scan = Some(loudness::scan_directory(dir));
+    }
+
+    pub fn scan_library(&mut self) {
+        if self.library_scan.is_none() {
+            self.library_scan = Some(library::scan(self.music_dir.clone()));
+        }
+    }
+
+    pub fn handle_scan_events(&mut self) {
+        update_scan(&mut self.loudness_scan, &mut self.error);
+
+        // the browser can sort the songs it could not before
+        if update_scan(&mut self.library_scan, &mut self.error) {
+            let _ = self.browser_state.update_state();
+            self.library_state.update_state();
+        }
+    }
+
+    pub fn scans(&self) -> impl Iterator<Item = (&str, &Scan)> {
+        [
+            ("Library scan", &self.library_scan),
+            ("Loudness scan", &self.loudness_scan),
+        ]
+        .into_iter()
+        .filter_map(|(name, scan)| scan.as_ref().map(|scan| (name, scan)))
+    }
+
+    pub fn handle_player_events(&mut self) {
+        while let Ok(event) = self.rx.try_recv() {
+            match event {
+                PlayerEvent::TRACKSTARTED(index) => {
+                    self.queue_state.set_playing(Some(index));
+                    self.position = Duration::ZERO;
+                }
+                PlayerEvent::TRACKFINISHED(_index) => {
+                    self.queue_state.set_playing(None);
+                    self.position = Duration::ZERO;
+                }
+                PlayerEvent::QUEUECHANGED(songs, playing) => {
+                    self.queue_state.set_queue(songs, playing)
+                }
+                PlayerEvent::VOLUME(volume, muted) => {
+                    self.state.volume = volume;
+                    self.state.muted = muted;
+                }
+                PlayerEvent::POSITION(position) => self.position = position,
+                PlayerEvent::PAUSED => self.player_state = PlayerState::PAUSED,
+                PlayerEvent::RESUMED => self.player_state = PlayerState::PLAYING,
+                PlayerEvent::MODE(shuffle, repeat) => {
+                    self.shuffle = shuffle;
+                    self.repeat = repeat;
+                }
+                PlayerEvent::CROSSFADE(crossfade) => self.state.crossfade = crossfade,
+                PlayerEvent::GAIN(mode) => self.state.gain_mode = mode,
+                PlayerEvent::ERROR(error) => self.error = Some(error),
+            }
+        }
+    }
+}
+
+/// Returns true once the scan finished, errors end up in the status bar
+fn update_scan(scan: &mut Option<Scan>, error: &mut Option<String>) -> bool {
+    let current = match scan.as_mut() {
+        Some(scan) => scan,
+        None => return false,
+    };
+
+    if let Some(e) = current.update().pop() {
+        *error = Some(e);
+    }
+
+    if current.finished {
+        *scan = None;
+        return true;
+    }
+
+    false
 }
 
 fn startup() -> Result<()> {
@@ -105,11 +487,23 @@ fn shutdown() -> Result<()> {
 }
 
 fn ui(app: &mut App, f: &mut Frame) -> Result<()> {
+    let status_height = 3 + app.scans().count() as u16;
+
+    let layout = Layout::default()
+        .constraints(vec![Constraint::Min(1), Constraint::Length(status_height)])
+        .split(f.size());
+
     match app.screen {
-        Screen::BROWSER => Ok(browser_list::browser(app, f)?),
-        Screen::WELCOME => Ok(screen_welcome(app, f)?),
-        _ => Ok(()),
+        Screen::BROWSER => browser_list::browser(app, f, layout[0])?,
+        Screen::WELCOME => screen_welcome(app, f, layout[0])?,
+        Screen::QUEUE => screen_queue(app, f, layout[0])?,
+        Screen::PLAYLISTS => screen_playlists(app, f, layout[0])?,
+        Screen::LIBRARY => screen_library(app, f, layout[0])?,
     }
+
+    status_bar::status_bar(app, f, layout[1]);
+
+    popup::popup(app, f)
 }
 
 // fn ui1(_app: &App, f: &mut Frame) {
@@ -131,19 +525,70 @@ fn ui(app: &mut App, f: &mut Frame) -> Result<()> {
 // }
 
 fn update(app: &mut App) -> Result<()> {
+    app.handle_player_events();
+    app.handle_scan_events();
+
     if event::poll(std::time::Duration::from_millis(250))? {
         if let Key(key) = event::read()? {
             if key.kind == event::KeyEventKind::Press {
+                // errors stay in the status bar until the next key press
+                app.error = None;
+
+                if app.popup.is_some() {
+                    app.popup_key(key);
+                    return Ok(());
+                }
+
+                if app.screen == Screen::BROWSER && app.browser_state.is_typing() {
+                    app.browser_state.filter_key(key.code);
+                    return Ok(());
+                }
+
                 match key.code {
                     Char('q') => app.running = false,
                     // change screens
                     Char('1') => app.screen = Screen::WELCOME,
+                    Char('2') => app.screen = Screen::QUEUE,
+                    Char('3') => {
+                        app.playlists_state.update_state()?;
+                        app.screen = Screen::PLAYLISTS;
+                    }
                     Char('4') => app.screen = Screen::BROWSER,
+                    Char('5') => {
+                        app.library_state.update_state();
+                        app.screen = Screen::LIBRARY;
+                    }
                     // player controls
                     Char('p') => app.tx.send(ThreadMessage {
                         command: ThreadCommand::PLAYPAUSE,
                         msg: None,
                     })?,
+                    // seeking
+                    Char('.') => app
+                        .send_command(ThreadCommand::SEEK(Seek::FORWARD(Duration::from_secs(5))))?,
+                    Char(',') => app.send_command(ThreadCommand::SEEK(Seek::BACKWARD(
+                        Duration::from_secs(5),
+                    )))?,
+                    Char('>') => app.send_command(ThreadCommand::SEEK(Seek::FORWARD(
+                        Duration::from_secs(30),
+                    )))?,
+                    Char('<') => app.send_command(ThreadCommand::SEEK(Seek::BACKWARD(
+                        Duration::from_secs(30),
+                    )))?,
+                    Char('t') => app.popup = Some(Popup::new(PopupKind::SEEK)),
+                    Char('/') => {
+                        app.popup = Some(Popup::new(PopupKind::SEARCH).with_search(Search::new()))
+                    }
+                    // volume
+                    Char('+') | Char('=') => app.send_command(ThreadCommand::VOLUME(Volume::UP))?,
+                    Char('-') => app.send_command(ThreadCommand::VOLUME(Volume::DOWN))?,
+                    Char('m') => app.send_command(ThreadCommand::VOLUME(Volume::MUTE))?,
+                    // playback modes
+                    Char('z') => app.send_command(ThreadCommand::SHUFFLE)?,
+                    Char('x') => app.send_command(ThreadCommand::REPEAT)?,
+                    Char('C') => app.send_command(ThreadCommand::CROSSFADE)?,
+                    Char('g') => app.send_command(ThreadCommand::GAIN)?,
+                    Char('U') => app.scan_library(),
                     Char('s') => app
                         .tx
                         .send(ThreadMessage {
@@ -158,7 +603,11 @@ fn update(app: &mut App) -> Result<()> {
                     match key.code {
                         Char('j') => app.browser_state.next(),
                         Char('k') => app.browser_state.previous(),
-                        Char('r') => app.browser_state.update_state()?,
+                        Char('r') => {
+                            if let Err(e) = app.browser_state.update_state() {
+                                app.error = Some(e.to_string());
+                            }
+                        }
                         Char('l') => match app.browser_state.get_file_type() {
                             FileType::FILE => {
                                 app.play_song()?;
@@ -168,77 +617,100 @@ fn update(app: &mut App) -> Result<()> {
                             }
                             FileType::NONE => {}
                         },
+                        Char('P') if app.browser_state.get_file_type() == FileType::FILE => {
+                            app.play_song_next()?
+                        }
+                        Char(' ') => app.toggle_mark(),
+                        Char('a') => app.add_to_playlist()?,
+                        Char('G') => app.scan_loudness(),
+                        Char('f') => app.browser_state.start_filter(),
+                        Char('n') => app.browser_state.next_match(true),
+                        Char('N') => app.browser_state.next_match(false),
+                        KeyCode::Esc if app.browser_state.is_filtering() => {
+                            app.browser_state.clear_filter()
+                        }
                         KeyCode::Backspace | Char('h') => app.browser_state.pop(),
                         _ => {}
                     }
                 }
-            }
-        }
-    }
-    Ok(())
-}
 
-fn init_player_thread(rx: std::sync::mpsc::Receiver<ThreadMessage>) -> Result<()> {
-    thread::spawn(move || {
-        let mut player_state = PlayerState::PAUSED;
-
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
-
-        loop {
-            let message = match rx.recv() {
-                Ok(message) => message,
-                Err(_e) => ThreadMessage {
-                    command: ThreadCommand::NONE,
-                    msg: None,
-                },
-            };
-
-            match message.command {
-                ThreadCommand::NONE => {}
-                ThreadCommand::PLAYPAUSE => match player_state {
-                    PlayerState::PLAYING => {
-                        sink.pause();
-                        player_state = PlayerState::PAUSED;
-                    }
-                    PlayerState::PAUSED => {
-                        sink.play();
-                        player_state = PlayerState::PLAYING;
+                if app.screen == Screen::QUEUE {
+                    match key.code {
+                        Char('j') => app.queue_state.next(),
+                        Char('k') => app.queue_state.previous(),
+                        Char('d') => {
+                            if let Some(i) = app.queue_state.selected() {
+                                app.send_command(ThreadCommand::REMOVE(i))?;
+                            }
+                        }
+                        Char('J') => {
+                            if let Some(i) = app.queue_state.selected() {
+                                if i + 1 < app.queue_state.len() {
+                                    app.send_command(ThreadCommand::MOVE(i, i + 1))?;
+                                    app.queue_state.next();
+                                }
+                            }
+                        }
+                        Char('K') => {
+                            if let Some(i) = app.queue_state.selected() {
+                                if i > 0 {
+                                    app.send_command(ThreadCommand::MOVE(i, i - 1))?;
+                                    app.queue_state.previous();
+                                }
+                            }
+                        }
+                        Char('c') => app.send_command(ThreadCommand::CLEAR)?,
+                        Char(' ') => app.toggle_mark(),
+                        Char('a') => app.add_to_playlist()?,
+                        _ => {}
                     }
-                },
-                ThreadCommand::SONG => {
-                    let file =
-                        BufReader::new(File::open(PathBuf::from(message.msg.unwrap())).unwrap());
+                }
 
-                    let source = Decoder::new(file).unwrap();
+                if app.screen == Screen::PLAYLISTS {
+                    match key.code {
+                        Char('j') => app.playlists_state.next(),
+                        Char('k') => app.playlists_state.previous(),
+                        Char('r') => app.playlists_state.update_state()?,
+                        Char('R') => app.rename_playlist(),
+                        Char('l') | KeyCode::Enter => app.play_playlist()?,
+                        _ => {}
+                    }
+                }
 
-                    sink.append(source);
-                    player_state = PlayerState::PLAYING;
+                if app.screen == Screen::LIBRARY {
+                    match key.code {
+                        Char('j') => app.library_state.next(),
+                        Char('k') => app.library_state.previous(),
+                        Char('r') => app.library_state.update_state(),
+                        Char('l') | KeyCode::Enter => app.open_library()?,
+                        Char('L') => app.enqueue(app.library_state.selected_songs())?,
+                        Char('v') => app.library_state.next_group(),
+                        Char(' ') => app.toggle_mark(),
+                        Char('a') => app.add_to_playlist()?,
+                        KeyCode::Backspace | Char('h') => app.library_state.back(),
+                        _ => {}
+                    }
                 }
-                ThreadCommand::SKIP => sink.skip_one(),
-                ThreadCommand::END => break,
             }
         }
-    });
-
+    }
     Ok(())
 }
 
-fn shutdown_player_thread(app: &App) -> Result<()> {
-    app.tx
-        .send(ThreadMessage {
-            command: ThreadCommand::END,
-            msg: None,
-        })
-        .unwrap();
-
-    Ok(())
+fn shutdown_player_thread(tx: &Sender<ThreadMessage>) {
+    let _ = tx.send(ThreadMessage {
+        command: ThreadCommand::END,
+        msg: None,
+    });
 }
 
-fn run() -> Result<()> {
-    let (tx, rx) = mpsc::channel::<ThreadMessage>();
+fn run(tx: Sender<ThreadMessage>, rx: Receiver<ThreadMessage>) -> Result<()> {
+    let config = Config::load()?;
+    let state = State::load();
+
+    let (event_tx, event_rx) = mpsc::channel::<PlayerEvent>();
 
-    init_player_thread(rx)?;
+    init_player_thread(rx, event_tx, &config, &state)?;
 
     let home_dir = match home::home_dir() {
         Some(path) => path,
@@ -255,10 +727,27 @@ fn run() -> Result<()> {
     let mut app = App {
         running: true,
         browser_state,
+        queue_state: QueueState::new(),
+        playlists_state: PlaylistsState::new(config.playlists_dir),
+        library_state: LibraryState::new(),
         screen: Screen::WELCOME,
+        popup: None,
+        marked: Vec::new(),
+        state,
+        player_state: PlayerState::PAUSED,
+        position: Duration::ZERO,
+        shuffle: false,
+        repeat: RepeatMode::OFF,
+        error: None,
+        music_dir: config.music_dir,
+        library_scan: None,
+        loudness_scan: None,
         tx,
+        rx: event_rx,
     };
 
+    app.scan_library();
+
     let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stderr()))?;
 
     loop {
@@ -269,7 +758,9 @@ fn run() -> Result<()> {
         })?;
 
         if !app.running {
-            shutdown_player_thread(&app)?;
+            shutdown_player_thread(&app.tx);
+            app.state.save()?;
+            library::save()?;
             break;
         }
     }
@@ -277,37 +768,61 @@ fn run() -> Result<()> {
     Ok(())
 }
 
-// pub fn install_hooks() -> Result<()> {
-//     // add any extra configuration you need to the hook builder
-//     let hook_builder = color_eyre::config::HookBuilder::default();
-//     let (panic_hook, eyre_hook) = hook_builder.into_hooks();
-//
-//     // convert from a color_eyre PanicHook to a standard panic hook
-//     let panic_hook = panic_hook.into_panic_hook();
-//     panic::set_hook(Box::new(move |panic_info| {
-//         shutdown().unwrap();
-//         panic_hook(panic_info);
-//     }));
-//
-//     // convert from a color_eyre EyreHook to a eyre ErrorHook
-//     let eyre_hook = eyre_hook.into_eyre_hook();
-//     eyre::set_hook(Box::new(move |error| {
-//         shutdown().unwrap();
-//         eyre_hook(error)
-//     }))?;
-//
-//     Ok(())
-// }
+/// Restores the terminal right before a report is printed, not when it is created
+struct RestoreTerminal(Box<dyn EyreHandler>);
+
+impl EyreHandler for RestoreTerminal {
+    fn debug(&self, error: &(dyn Error + 'static), f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _ = shutdown();
+        self.0.debug(error, f)
+    }
+
+    fn display(&self, error: &(dyn Error + 'static), f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.display(error, f)
+    }
+
+    fn track_caller(&mut self, location: &'static Location<'static>) {
+        self.0.track_caller(location)
+    }
+}
+
+pub fn install_hooks(tx: Sender<ThreadMessage>) -> Result<()> {
+    let hook_builder = color_eyre::config::HookBuilder::default();
+    let (panic_hook, eyre_hook) = hook_builder.into_hooks();
+
+    // convert from a color_eyre PanicHook to a standard panic hook
+    let panic_hook = panic_hook.into_panic_hook();
+    panic::set_hook(Box::new(move |panic_info| {
+        shutdown_player_thread(&tx);
+        let _ = shutdown();
+        panic_hook(panic_info);
+
+        // the UI would carry on drawing to the terminal that was just restored
+        if thread::current().name() != Some("main") {
+            process::exit(101);
+        }
+    }));
+
+    // convert from a color_eyre EyreHook to a eyre ErrorHook
+    let eyre_hook = eyre_hook.into_eyre_hook();
+    eyre::set_hook(Box::new(move |error| {
+        Box::new(RestoreTerminal(eyre_hook(error)))
+    }))?;
+
+    Ok(())
+}
 
 fn main() -> Result<()> {
     // env::set_var("RUST_BACKTRACE", "full");
 
+    let (tx, rx) = mpsc::channel::<ThreadMessage>();
+
+    install_hooks(tx.clone())?;
     startup()?;
-    color_eyre::install()?;
-    // install_hooks()?;
 
-    let result = run();
+    let result = run(tx.clone(), rx);
 
+    shutdown_player_thread(&tx);
     shutdown()?;
 
     result?;
@@ -0,0 +1,252 @@
+use audiopus::{coder::Decoder, packet::Packet, Channels, MutSignals, SampleRate};
+use color_eyre::eyre::{eyre, Result};
+use ogg::PacketReader;
+use rodio::Source;
+use std::io::{Read, Seek};
+use std::time::Duration;
+
+/// Opus always decodes at 48 kHz, whatever rate the file was made from
+const SAMPLE_RATE: u32 = 48000;
+/// The longest frame an Opus packet can hold, 120 ms
+const MAX_FRAME: usize = 5760;
+
+/// Decodes an Ogg Opus stream, which symphonia can demux but not decode
+pub struct OpusSource<R: Read + Seek> {
+    packets: PacketReader<R>,
+    decoder: Decoder,
+    channels: u16,
+    buffer: Vec<i16>,
+    position: usize,
+    len: usize,
+    /// samples per channel still to drop from the start, the encoder's pre-skip
+    skip: usize,
+    /// samples per channel decoded so far, pre-skip included
+    decoded: u64,
+}
+
+impl<R: Read + Seek> OpusSource<R> {
+    pub fn new(reader: R) -> Result<OpusSource<R>> {
+        let mut packets = PacketReader::new(reader);
+
+        let head = packets
+            .read_packet()?
+            .ok_or_else(|| eyre!("Empty Ogg stream"))?;
+        let (channels, skip) = parse_head(&head.data)?;
+
+        // the comment header, the tags are read elsewhere
+        packets.read_packet()?;
+
+        let decoder = Decoder::new(
+            SampleRate::Hz48000,
+            if channels == 1 {
+                Channels::Mono
+            } else {
+                Channels::Stereo
+            },
+        )?;
+
+        Ok(OpusSource {
+            packets,
+            decoder,
+            channels,
+            buffer: vec![0; MAX_FRAME * channels as usize],
+            position: 0,
+            len: 0,
+            skip,
+            decoded: 0,
+        })
+    }
+
+    /// Decodes the next packet into the buffer, false at the end of the stream
+    fn decode_packet(&mut self) -> bool {
+        let packet = match self.packets.read_packet() {
+            Ok(Some(packet)) => packet,
+            _ => return false,
+        };
+
+        let channels = self.channels as usize;
+        let decoded = match Packet::try_from(&packet.data).and_then(|data| {
+            let output = MutSignals::try_from(&mut self.buffer)?;
+            self.decoder.decode(Some(data), output, false)
+        }) {
+            Ok(decoded) => decoded,
+            // a damaged packet is dropped rather than ending the song
+            Err(_) => 0,
+        };
+
+        self.decoded += decoded as u64;
+        let mut samples = decoded;
+
+        // the granule position of the last page marks where the audio really ends
+        if packet.last_in_stream() && packet.absgp_page() < self.decoded {
+            let padding = (self.decoded - packet.absgp_page()) as usize;
+            samples = samples.saturating_sub(padding);
+        }
+
+        let skipped = self.skip.min(samples);
+        self.skip -= skipped;
+        self.position = skipped * channels;
+        self.len = samples * channels;
+
+        true
+    }
+}
+
+/// Channel count and pre-skip from the identification header
+fn parse_head(data: &[u8]) -> Result<(u16, usize)> {
+    if data.len() < 19 || !data.starts_with(b"OpusHead") {
+        return Err(eyre!("Not an Opus stream"));
+    }
+
+    let channels = data[9] as u16;
+    let skip = u16::from_le_bytes([data[10], data[11]]) as usize;
+    let mapping = data[18];
+
+    // surround streams need the multistream decoder
+    if mapping != 0 || !(1..=2).contains(&channels) {
+        return Err(eyre!(
+            "Opus streams with {} channels are not supported",
+            channels
+        ));
+    }
+
+    Ok((channels, skip))
+}
+
+impl<R: Read + Seek> Iterator for OpusSource<R> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        while self.position >= self.len {
+            if !self.decode_packet() {
+                return None;
+            }
+        }
+
+        let sample = self.buffer[self.position];
+        self.position += 1;
+
+        Some(sample)
+    }
+}
+
+impl<R: Read + Seek> Source for OpusSource<R> {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use audiopus::{coder::Encoder, Application};
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+    use std::io::Cursor;
+
+    /// An Ogg Opus stream holding `frames` 20 ms frames of a quiet tone
+    pub fn encode(channels: u16, frames: usize, tags: &[&str]) -> Vec<u8> {
+        let encoder = Encoder::new(
+            SampleRate::Hz48000,
+            if channels == 1 {
+                Channels::Mono
+            } else {
+                Channels::Stereo
+            },
+            Application::Audio,
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        let mut writer = PacketWriter::new(&mut out);
+
+        let mut head = b"OpusHead\x01".to_vec();
+        head.push(channels as u8);
+        head.extend(312u16.to_le_bytes());
+        head.extend(48000u32.to_le_bytes());
+        head.extend([0, 0, 0]);
+        writer
+            .write_packet(head.into_boxed_slice(), 1, PacketWriteEndInfo::EndPage, 0)
+            .unwrap();
+
+        let mut comments = b"OpusTags".to_vec();
+        comments.extend(4u32.to_le_bytes());
+        comments.extend(b"test");
+        comments.extend((tags.len() as u32).to_le_bytes());
+        for tag in tags {
+            comments.extend((tag.len() as u32).to_le_bytes());
+            comments.extend(tag.as_bytes());
+        }
+        writer
+            .write_packet(
+                comments.into_boxed_slice(),
+                1,
+                PacketWriteEndInfo::EndPage,
+                0,
+            )
+            .unwrap();
+
+        let frame = 960 * channels as usize;
+        for i in 0..frames {
+            let input = (0..frame)
+                .map(|n| ((n as f32 * 0.05).sin() * 3000.0) as i16)
+                .collect::<Vec<_>>();
+            let mut packet = vec![0; 4000];
+            let len = encoder.encode(&input, &mut packet).unwrap();
+            packet.truncate(len);
+
+            let end = if i + 1 == frames {
+                PacketWriteEndInfo::EndStream
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            // the last frame is only half used, as the encoder padded it
+            let granule = 312 + (i as u64 + 1) * 960 - if i + 1 == frames { 480 } else { 0 };
+            writer
+                .write_packet(packet.into_boxed_slice(), 1, end, granule)
+                .unwrap();
+        }
+
+        drop(writer);
+        out
+    }
+
+    #[test]
+    fn drops_the_pre_skip_and_end_padding() {
+        let source = OpusSource::new(Cursor::new(encode(2, 10, &[]))).unwrap();
+        assert_eq!(source.channels(), 2);
+        assert_eq!(source.sample_rate(), 48000);
+
+        assert_eq!(source.count(), (10 * 960 - 480) * 2);
+    }
+
+    #[test]
+    fn mono_streams_decode() {
+        let source = OpusSource::new(Cursor::new(encode(1, 5, &[]))).unwrap();
+        assert_eq!(source.channels(), 1);
+
+        let samples = source.collect::<Vec<_>>();
+        assert_eq!(samples.len(), 5 * 960 - 480);
+        assert!(samples.iter().any(|sample| sample.abs() > 1000));
+    }
+
+    #[test]
+    fn rejects_other_streams() {
+        assert!(parse_head(b"\x01vorbis\x00\x00\x00\x00\x02\x44\xac\x00\x00").is_err());
+
+        let mut surround = b"OpusHead\x01\x06\x38\x01\x80\xbb\x00\x00\x00\x00\x01".to_vec();
+        surround.extend([4, 2, 0, 4, 1, 2, 3, 5]);
+        assert!(parse_head(&surround).is_err());
+    }
+}
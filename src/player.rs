@@ -0,0 +1,497 @@
+use crate::config::{Config, State};
+use crate::format::AudioFormat;
+use crate::gapless::{gapless, BoxedSource, Crossfade, GaplessHandle, Track};
+use crate::opus::OpusSource;
+use crate::playlist::PlaylistEntry;
+use crate::queue::{Queue, RepeatMode};
+use crate::replaygain::GainMode;
+use crate::song::Song;
+use color_eyre::eyre::{Report, Result};
+use rodio::{Decoder, OutputStream, Sink, Source};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::mpsc::{Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlayerState {
+    PLAYING,
+    PAUSED,
+}
+
+pub enum ThreadCommand {
+    NONE,
+    SONG,
+    PLAYNEXT,
+    PLAYPAUSE,
+    REMOVE(usize),
+    MOVE(usize, usize),
+    CLEAR,
+    SEEK(Seek),
+    VOLUME(Volume),
+    END,
+    SKIP,
+    SHUFFLE,
+    REPEAT,
+    CROSSFADE,
+    GAIN,
+}
+
+pub enum Seek {
+    FORWARD(Duration),
+    BACKWARD(Duration),
+    TO(Duration),
+}
+
+pub enum Volume {
+    UP,
+    DOWN,
+    MUTE,
+}
+
+const VOLUME_STEP: f32 = 0.05;
+
+pub struct ThreadMessage {
+    pub command: ThreadCommand,
+    pub msg: Option<String>,
+}
+
+/// Sent from the player thread back to the UI
+pub enum PlayerEvent {
+    TRACKSTARTED(usize),
+    TRACKFINISHED(usize),
+    QUEUECHANGED(Vec<Song>, Option<usize>),
+    VOLUME(f32, bool),
+    POSITION(Duration),
+    PAUSED,
+    RESUMED,
+    MODE(bool, RepeatMode),
+    CROSSFADE(bool),
+    GAIN(GainMode),
+    ERROR(String),
+}
+
+fn load_song(path: Option<String>, tx: &Sender<PlayerEvent>) -> Option<Song> {
+    let path = PathBuf::from(path?);
+
+    if AudioFormat::detect(&path).is_none() {
+        let _ = tx.send(PlayerEvent::ERROR(format!(
+            "{}: unsupported format",
+            path.display()
+        )));
+        return None;
+    }
+
+    // formats without tags that can be read still play, titled by their file name
+    Some(PlaylistEntry::new(path).into_song())
+}
+
+/// Accepts `ss`, `mm:ss` and `hh:mm:ss`
+pub fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut seconds: u64 = 0;
+
+    for (i, part) in timestamp.trim().split(':').enumerate() {
+        if i > 2 {
+            return None;
+        }
+
+        seconds = seconds * 60 + part.trim().parse::<u64>().ok()?;
+    }
+
+    Some(Duration::from_secs(seconds))
+}
+
+fn decode(song: &Song) -> Result<BoxedSource> {
+    let path = song.get_path();
+    let file = BufReader::new(File::open(&path)?);
+
+    Ok(match AudioFormat::detect(&path) {
+        Some(AudioFormat::OPUS) => Box::new(OpusSource::new(file)?),
+        _ => Box::new(Decoder::new(file)?),
+    })
+}
+
+fn track(song: &Song, source: BoxedSource, mode: GainMode, preamp: f32) -> Track {
+    Track {
+        source,
+        gain: song.replay_gain().factor(mode, preamp),
+    }
+}
+
+fn decode_error(song: &Song, e: Report) -> PlayerEvent {
+    PlayerEvent::ERROR(format!("{}: {}", song.get_path().display(), e))
+}
+
+/// Albums that flow from one track into the next are not crossfaded
+fn same_album(current: &Song, next: &Song) -> bool {
+    current.album().is_some()
+        && current.album() == next.album()
+        && current.artist() == next.artist()
+}
+
+/// Fades out over the last `length` of the current song, `offset` is where its decoding started
+fn crossfade(current: &Song, next: &Song, length: Duration, offset: Duration) -> Option<Crossfade> {
+    if same_album(current, next) {
+        return None;
+    }
+
+    let duration = Duration::from_secs(current.duration()? as u64);
+
+    Some(Crossfade {
+        at: duration.saturating_sub(length).saturating_sub(offset),
+        length,
+    })
+}
+
+/// Keeps the channel open so the UI can still send commands
+fn wait_for_end(rx: &Receiver<ThreadMessage>) {
+    while let Ok(message) = rx.recv() {
+        if let ThreadCommand::END = message.command {
+            break;
+        }
+    }
+}
+
+pub fn init_player_thread(
+    rx: Receiver<ThreadMessage>,
+    tx: Sender<PlayerEvent>,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
+    let mut volume = state.volume.clamp(0.0, 1.0);
+    let mut muted = state.muted;
+    let mut crossfade_enabled = state.crossfade;
+    let crossfade_length = Duration::from_secs(config.crossfade);
+    let mut gain_mode = state.gain_mode;
+    let preamp = config.preamp;
+
+    thread::spawn(move || {
+        let mut player_state = PlayerState::PAUSED;
+
+        // without an output device there is nothing to play, but the UI keeps running
+        let (_stream, stream_handle) = match OutputStream::try_default() {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = tx.send(PlayerEvent::ERROR(format!("Audio output: {}", e)));
+                wait_for_end(&rx);
+                return;
+            }
+        };
+        let sink = match Sink::try_new(&stream_handle) {
+            Ok(sink) => sink,
+            Err(e) => {
+                let _ = tx.send(PlayerEvent::ERROR(format!("Audio output: {}", e)));
+                wait_for_end(&rx);
+                return;
+            }
+        };
+        sink.set_volume(if muted { 0.0 } else { volume });
+
+        let mut queue = Queue::new();
+        // set while the current song of the queue is in the sink
+        let mut playing: Option<GaplessHandle> = None;
+        // how many preloaded songs have taken over in the sink so far
+        let mut handovers = 0;
+        // where playback started in the current song when it had to be decoded again to seek
+        let mut offset = Duration::ZERO;
+        // the song was skipped rather than finished, so repeat one does not apply
+        let mut skipped = false;
+
+        // what the UI was last told
+        let mut reported_state = player_state;
+        let mut reported_position = Duration::ZERO;
+
+        loop {
+            let message = match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(message) => message,
+                Err(_e) => ThreadMessage {
+                    command: ThreadCommand::NONE,
+                    msg: None,
+                },
+            };
+
+            let mut queue_changed = false;
+
+            // the preloaded song took over inside the sink, catch up before the queue changes
+            if let Some(handle) = &playing {
+                if handle.started() != handovers {
+                    handovers = handle.started();
+
+                    if let Some(current) = queue.current() {
+                        let _ = tx.send(PlayerEvent::TRACKFINISHED(current));
+                    }
+
+                    match queue.start_preloaded() {
+                        Some(current) => {
+                            offset = Duration::ZERO;
+                            skipped = false;
+                            let _ = tx.send(PlayerEvent::TRACKSTARTED(current));
+                        }
+                        None => {
+                            // it was removed from the queue just as it started
+                            sink.clear();
+                            playing = None;
+                            queue.advance();
+                        }
+                    }
+
+                    queue_changed = true;
+                }
+            }
+
+            match message.command {
+                ThreadCommand::NONE => {}
+                ThreadCommand::PLAYPAUSE => match player_state {
+                    PlayerState::PLAYING => {
+                        sink.pause();
+                        player_state = PlayerState::PAUSED;
+                    }
+                    PlayerState::PAUSED => {
+                        sink.play();
+                        player_state = PlayerState::PLAYING;
+                    }
+                },
+                ThreadCommand::SONG => {
+                    if let Some(song) = load_song(message.msg, &tx) {
+                        if queue.current().is_none() {
+                            player_state = PlayerState::PLAYING;
+                        }
+
+                        queue.push(song);
+                        queue_changed = true;
+                    }
+                }
+                ThreadCommand::PLAYNEXT => {
+                    if let Some(song) = load_song(message.msg, &tx) {
+                        if queue.current().is_none() {
+                            player_state = PlayerState::PLAYING;
+                        }
+
+                        queue.play_next(song);
+                        queue_changed = true;
+                    }
+                }
+                ThreadCommand::REMOVE(index) => {
+                    if queue.remove(index) {
+                        sink.clear();
+                        playing = None;
+                    }
+                    queue_changed = true;
+                }
+                ThreadCommand::MOVE(from, to) => {
+                    queue.move_song(from, to);
+                    queue_changed = true;
+                }
+                ThreadCommand::CLEAR => {
+                    queue.clear();
+                    sink.clear();
+                    playing = None;
+                    queue_changed = true;
+                }
+                ThreadCommand::SEEK(seek) => {
+                    if let (Some(song), Some(handle)) = (queue.current_song(), &playing) {
+                        let position = offset + handle.position();
+
+                        let target = match seek {
+                            Seek::FORWARD(by) => position + by,
+                            Seek::BACKWARD(by) => position.saturating_sub(by),
+                            Seek::TO(to) => to,
+                        };
+
+                        let duration = song.duration().map(|d| Duration::from_secs(d as u64));
+
+                        if duration.is_some_and(|duration| target >= duration) {
+                            sink.skip_one();
+                        } else if sink.try_seek(target).is_ok() {
+                            offset = Duration::ZERO;
+                        } else {
+                            // the format can not seek, decode it again and skip ahead instead
+                            match decode(song) {
+                                Ok(source) => {
+                                    let (source, handle) = gapless(track(
+                                        song,
+                                        Box::new(source.skip_duration(target)),
+                                        gain_mode,
+                                        preamp,
+                                    ));
+
+                                    sink.clear();
+                                    sink.append(source);
+                                    playing = Some(handle);
+                                    handovers = 0;
+                                    queue.set_preloaded(None);
+                                    offset = target;
+
+                                    if player_state == PlayerState::PLAYING {
+                                        sink.play();
+                                    }
+                                }
+                                Err(e) => {
+                                    let _ = tx.send(decode_error(song, e));
+                                }
+                            }
+                        }
+                    }
+                }
+                ThreadCommand::VOLUME(change) => {
+                    match change {
+                        Volume::UP => {
+                            volume = (volume + VOLUME_STEP).min(1.0);
+                            muted = false;
+                        }
+                        Volume::DOWN => {
+                            volume = (volume - VOLUME_STEP).max(0.0);
+                            muted = false;
+                        }
+                        Volume::MUTE => muted = !muted,
+                    }
+
+                    sink.set_volume(if muted { 0.0 } else { volume });
+                    let _ = tx.send(PlayerEvent::VOLUME(volume, muted));
+                }
+                ThreadCommand::SKIP => {
+                    if playing.is_some() {
+                        sink.skip_one();
+                        skipped = true;
+                    }
+                }
+                ThreadCommand::SHUFFLE => {
+                    queue.set_shuffle(!queue.shuffled());
+                    let _ = tx.send(PlayerEvent::MODE(queue.shuffled(), queue.repeat()));
+                }
+                ThreadCommand::REPEAT => {
+                    queue.set_repeat(queue.repeat().next());
+                    let _ = tx.send(PlayerEvent::MODE(queue.shuffled(), queue.repeat()));
+                }
+                ThreadCommand::CROSSFADE => {
+                    crossfade_enabled = !crossfade_enabled;
+                    // hand the next song over again with the new setting
+                    queue.reload_preloaded();
+                    let _ = tx.send(PlayerEvent::CROSSFADE(crossfade_enabled));
+                }
+                ThreadCommand::GAIN => {
+                    gain_mode = gain_mode.next();
+
+                    if let (Some(song), Some(handle)) = (queue.current_song(), &playing) {
+                        handle.set_gain(song.replay_gain().factor(gain_mode, preamp));
+                    }
+                    queue.reload_preloaded();
+
+                    let _ = tx.send(PlayerEvent::GAIN(gain_mode));
+                }
+                ThreadCommand::END => break,
+            }
+
+            // the song that was playing has finished with nothing preloaded, or was skipped
+            if playing.is_some() && sink.empty() {
+                playing = None;
+
+                if let Some(current) = queue.current() {
+                    let _ = tx.send(PlayerEvent::TRACKFINISHED(current));
+                }
+
+                if skipped {
+                    queue.skip();
+                } else {
+                    queue.advance();
+                }
+                skipped = false;
+                queue_changed = true;
+            }
+
+            // songs that can not be opened or decoded are skipped, stop once every song failed
+            let mut failed = 0;
+            while playing.is_none() {
+                if failed > 0 && failed >= queue.songs().len() {
+                    queue.stop();
+                    break;
+                }
+
+                let (current, song) = match (queue.current(), queue.current_song()) {
+                    (Some(current), Some(song)) => (current, song),
+                    _ => break,
+                };
+
+                match decode(song) {
+                    Ok(source) => {
+                        let (source, handle) = gapless(track(song, source, gain_mode, preamp));
+
+                        sink.append(source);
+                        playing = Some(handle);
+                        handovers = 0;
+                        queue.set_preloaded(None);
+                        offset = Duration::ZERO;
+                        skipped = false;
+                        let _ = tx.send(PlayerEvent::TRACKSTARTED(current));
+
+                        if player_state == PlayerState::PLAYING {
+                            sink.play();
+                        }
+                    }
+                    Err(e) => {
+                        let _ = tx.send(decode_error(song, e));
+                        queue.skip();
+                        queue_changed = true;
+                        failed += 1;
+                    }
+                }
+            }
+
+            // decode the song that comes next ahead of time so it starts without a gap
+            if let Some(handle) = &playing {
+                if queue.needs_preload() {
+                    let upcoming = queue.upcoming();
+                    let next = upcoming.and_then(|i| queue.songs().get(i));
+
+                    // a song that fails to decode is reported when it is loaded on its own
+                    let next_track = next.and_then(|song| {
+                        let source = decode(song).ok()?;
+                        Some(track(song, source, gain_mode, preamp))
+                    });
+
+                    let fade = match (queue.current_song(), next) {
+                        (Some(current), Some(next)) if crossfade_enabled => {
+                            crossfade(current, next, crossfade_length, offset)
+                        }
+                        _ => None,
+                    };
+
+                    if handle.set_next(next_track, fade, handovers) {
+                        queue.set_preloaded(upcoming);
+                    }
+                }
+            }
+
+            if queue_changed {
+                let _ = tx.send(PlayerEvent::QUEUECHANGED(
+                    queue.songs().clone(),
+                    queue.current(),
+                ));
+            }
+
+            if player_state != reported_state {
+                reported_state = player_state;
+                let _ = tx.send(match player_state {
+                    PlayerState::PLAYING => PlayerEvent::RESUMED,
+                    PlayerState::PAUSED => PlayerEvent::PAUSED,
+                });
+            }
+
+            let position = match &playing {
+                Some(handle) => offset + handle.position(),
+                None => Duration::ZERO,
+            };
+
+            if position != reported_position {
+                reported_position = position;
+                let _ = tx.send(PlayerEvent::POSITION(position));
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -1,8 +1,11 @@
-use crate::song::{Song, SongBuilder};
+use crate::library;
+use crate::replaygain::ReplayGain;
+use crate::song::{Inferred, Song, SongBuilder};
+use crate::{m3u, pls, xspf};
 use color_eyre::eyre::{eyre, Result};
 use permutation::permutation;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -10,6 +13,8 @@ pub struct Playlist {
     name: String,
     length: u32,
     songs: Vec<Song>,
+    missing: Vec<PlaylistEntry>,
+    file: Option<PathBuf>,
 }
 
 #[allow(dead_code)]
@@ -17,6 +22,111 @@ pub struct PlaylistBuilder {
     name: String,
     length: u32,
     songs: Vec<Song>,
+    missing: Vec<PlaylistEntry>,
+    file: Option<PathBuf>,
+}
+
+/// A single track as it is written in a playlist file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<u32>,
+}
+
+impl PlaylistEntry {
+    pub fn new(path: PathBuf) -> Self {
+        PlaylistEntry {
+            path,
+            title: None,
+            artist: None,
+            album: None,
+            duration: None,
+        }
+    }
+
+    /// Titles are written as `Artist - Title` so other players show the artist. It is only cut
+    /// off again when the playlist names the artist on its own, so "Intro - Live" stays whole.
+    pub fn strip_artist(&mut self) {
+        if let (Some(artist), Some(title)) = (&self.artist, &mut self.title) {
+            if let Some(rest) = title.strip_prefix(&format!("{} - ", artist)) {
+                *title = rest.to_string();
+            }
+        }
+    }
+
+    /// Tags in the file win, the playlist metadata is used for anything they do not have
+    pub fn into_song(self) -> Song {
+        if let Ok(song) = library::song(&self.path) {
+            return song.with_known(self.title, self.artist, self.album, self.duration);
+        }
+
+        self.into_listed_song()
+    }
+
+    /// The song as the playlist describes it, for files that can not be read
+    fn into_listed_song(self) -> Song {
+        let title = match self.title {
+            Some(title) => title,
+            None => self
+                .path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+
+        // only what the playlist filled in counts as inferred
+        let inferred = Inferred {
+            title: true,
+            artist: self.artist.is_some(),
+            album: self.album.is_some(),
+            duration: self.duration.is_some(),
+        };
+
+        SongBuilder::new()
+            .title(title)
+            .artist(self.artist)
+            .album(self.album)
+            .duration(self.duration)
+            .replay_gain(ReplayGain::read(&self.path))
+            .inferred(inferred)
+            .path(self.path)
+            .build()
+    }
+}
+
+/// Entries in playlist files are relative to the directory the playlist is in
+pub fn resolve_path(dir: &Path, entry: &str) -> PathBuf {
+    let path = PathBuf::from(entry);
+
+    if path.is_absolute() {
+        path
+    } else {
+        dir.join(path)
+    }
+}
+
+/// Writes songs inside the playlist directory as relative paths so the playlist can be moved
+pub fn relative_path(dir: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix(dir) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_e) => path.to_path_buf(),
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+}
+
+pub fn is_playlist_file(path: &Path) -> bool {
+    matches!(
+        extension(path).as_deref(),
+        Some("m3u") | Some("m3u8") | Some("pls") | Some("xspf")
+    )
 }
 
 #[allow(dead_code)]
@@ -24,6 +134,82 @@ impl Playlist {
     pub fn songs(&self) -> &Vec<Song> {
         &self.songs
     }
+
+    pub fn missing(&self) -> &Vec<PlaylistEntry> {
+        &self.missing
+    }
+
+    /// The playlist file this playlist is saved to, None for directories
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    /// Missing entries are kept at the end so saving does not drop them
+    pub fn save_as(&self, path: &Path) -> Result<()> {
+        let mut songs = self.songs.clone();
+        songs.extend(
+            self.missing
+                .iter()
+                .cloned()
+                .map(PlaylistEntry::into_listed_song),
+        );
+
+        match extension(path).as_deref() {
+            Some("m3u") | Some("m3u8") => m3u::write(path, &songs),
+            Some("pls") => pls::write(path, &songs),
+            Some("xspf") => xspf::write(path, &self.name, &songs),
+            _ => Err(eyre!("Playlist: unsupported format {}", path.display())),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        match &self.file {
+            Some(file) => self.save_as(file),
+            None => Err(eyre!("Playlist: {} is a directory", self.name)),
+        }
+    }
+
+    pub fn add_song(&mut self, song: Song) {
+        self.songs.push(song);
+        self.length = self.songs.len() as u32;
+    }
+
+    /// Renames the playlist file on disk, keeping its extension
+    pub fn rename(&mut self, name: &str) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Err(eyre!("Playlist: {} is a directory", self.name)),
+        };
+
+        if name.contains(std::path::MAIN_SEPARATOR) || name.contains('/') {
+            return Err(eyre!("Playlist: {} contains a path separator", name));
+        }
+
+        // set_extension would cut names with a dot in them, like "Vol. 2"
+        let new_file = match file.extension() {
+            Some(ext) => file.with_file_name(format!("{}.{}", name, ext.to_string_lossy())),
+            None => file.with_file_name(name),
+        };
+
+        if new_file.exists() {
+            return Err(eyre!("Playlist: {} already exists", new_file.display()));
+        }
+
+        fs::rename(file, &new_file)?;
+
+        self.name = name.to_string();
+        self.file = Some(new_file);
+
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
 }
 
 #[allow(dead_code)]
@@ -33,9 +219,27 @@ impl PlaylistBuilder {
             name: String::from(""),
             length: 0,
             songs: vec![],
+            missing: vec![],
+            file: None,
         }
     }
 
+    pub fn name(mut self, name: &str) -> PlaylistBuilder {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn file(mut self, path: PathBuf) -> PlaylistBuilder {
+        self.file = Some(path);
+        self
+    }
+
+    pub fn songs(mut self, songs: Vec<Song>) -> PlaylistBuilder {
+        self.length = songs.len() as u32;
+        self.songs = songs;
+        self
+    }
+
     pub fn from_path(mut self, path: &str) -> Result<PlaylistBuilder> {
         if !PathBuf::from(path).exists() {
             return Err(eyre!("Path does not exist"));
@@ -56,14 +260,10 @@ impl PlaylistBuilder {
             })
             .collect::<Vec<String>>();
 
+        // skip anything that is not a song, like cover art
         self.songs = names
             .iter()
-            .map(|name| {
-                SongBuilder::new()
-                    .from_path(&PathBuf::from(format!("{}/{}", path, name)))
-                    .expect("REASON")
-                    .build()
-            })
+            .filter_map(|name| library::song(&PathBuf::from(format!("{}/{}", path, name))).ok())
             .collect::<Vec<Song>>();
 
         self.name = match path.split("/").last() {
@@ -76,6 +276,34 @@ impl PlaylistBuilder {
         Ok(self)
     }
 
+    pub fn from_file(mut self, path: &Path) -> Result<PlaylistBuilder> {
+        let entries = match extension(path).as_deref() {
+            Some("m3u") | Some("m3u8") => m3u::read(path)?,
+            Some("pls") => pls::read(path)?,
+            Some("xspf") => xspf::read(path)?,
+            _ => return Err(eyre!("Playlist: unsupported format {}", path.display())),
+        };
+
+        for entry in entries {
+            if entry.path.exists() {
+                self.songs.push(entry.into_song());
+            } else {
+                self.missing.push(entry);
+            }
+        }
+
+        self.name = match path.file_stem() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return Err(eyre!("Playlist: file name")),
+        };
+
+        self.file = Some(path.to_path_buf());
+
+        self.length = self.songs.len() as u32;
+
+        Ok(self)
+    }
+
     pub fn sort_by_track_number(mut self) -> PlaylistBuilder {
         let track_numbers: Vec<&u16> = self
             .songs
@@ -86,6 +314,10 @@ impl PlaylistBuilder {
             })
             .collect();
 
+        if self.songs.len() != track_numbers.len() {
+            return self;
+        }
+
         let permutation = permutation::sort(&track_numbers);
 
         self.songs = permutation.apply_slice(&self.songs);
@@ -98,6 +330,151 @@ impl PlaylistBuilder {
             name: self.name,
             length: self.length,
             songs: self.songs,
+            missing: self.missing,
+            file: self.file,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn playlist(file: PathBuf) -> Playlist {
+        fs::write(&file, "#EXTM3U\n").unwrap();
+
+        PlaylistBuilder::new().name("Mix").file(file).build()
+    }
+
+    #[test]
+    fn rename_keeps_dots_and_the_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut playlist = playlist(dir.path().join("Mix.m3u"));
+
+        playlist.rename("Vol. 2").unwrap();
+
+        assert_eq!(playlist.name(), "Vol. 2");
+        assert!(dir.path().join("Vol. 2.m3u").is_file());
+        assert!(!dir.path().join("Mix.m3u").exists());
+    }
+
+    #[test]
+    fn rename_refuses_paths_and_existing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut playlist = playlist(dir.path().join("Mix.m3u"));
+        fs::write(dir.path().join("Taken.m3u"), "#EXTM3U\n").unwrap();
+
+        assert!(playlist.rename("../Mix").is_err());
+        assert!(playlist.rename("Taken").is_err());
+
+        assert_eq!(playlist.name(), "Mix");
+        assert!(dir.path().join("Mix.m3u").is_file());
+    }
+
+    #[test]
+    fn unreadable_entries_only_infer_what_the_playlist_has() {
+        let entry = PlaylistEntry {
+            artist: Some(String::from("Nina Simone")),
+            duration: Some(615),
+            ..PlaylistEntry::new(PathBuf::from("/gone/Sinnerman.flac"))
+        };
+
+        let song = entry.into_song();
+        assert_eq!(song.title(), "Sinnerman");
+        assert_eq!(
+            song.inferred(),
+            Inferred {
+                title: true,
+                artist: true,
+                album: false,
+                duration: true,
+            }
+        );
+    }
+
+    #[test]
+    fn saving_keeps_what_is_known_about_missing_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("Mix.m3u");
+        fs::write(
+            &file,
+            "#EXTM3U\n#EXTINF:215,Nina Simone - Sinnerman\n/gone/Sinnerman.flac\n",
+        )
+        .unwrap();
+
+        let playlist = PlaylistBuilder::new().from_file(&file).unwrap().build();
+        assert_eq!(playlist.missing().len(), 1);
+
+        let copy = dir.path().join("Copy.m3u");
+        playlist.save_as(&copy).unwrap();
+
+        assert_eq!(m3u::read(&copy).unwrap(), m3u::read(&file).unwrap());
+    }
+
+    /// Relative and absolute paths, UTF-8, missing fields and a dash in a title without artist
+    fn songs(dir: &Path) -> Vec<Song> {
+        vec![
+            SongBuilder::new()
+                .title(String::from("Wildlife Analysis"))
+                .artist(Some(String::from("Boards of Canada")))
+                .album(Some(String::from("Music Has the Right to Children")))
+                .duration(Some(77))
+                .path(dir.join("Boards of Canada/01 Wildlife Analysis.mp3"))
+                .build(),
+            SongBuilder::new()
+                .title(String::from("Glósóli"))
+                .artist(Some(String::from("Sigur Rós")))
+                .album(None)
+                .duration(Some(375))
+                .path(PathBuf::from("/music/Sigur Rós/Takk.../02 Glósóli.flac"))
+                .build(),
+            SongBuilder::new()
+                .title(String::from("Intro - Live"))
+                .artist(None)
+                .album(None)
+                .duration(None)
+                .path(dir.join("Intro - Live.ogg"))
+                .build(),
+        ]
+    }
+
+    #[test]
+    fn songs_round_trip_through_every_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let songs = songs(dir.path());
+
+        for ext in ["m3u", "m3u8", "pls", "xspf"] {
+            let file = dir.path().join(format!("Mix.{}", ext));
+            PlaylistBuilder::new()
+                .name("Mix & Friends")
+                .file(file.clone())
+                .songs(songs.clone())
+                .build()
+                .save()
+                .unwrap();
+
+            // none of the songs exist, so they come back as the playlist describes them
+            let playlist = PlaylistBuilder::new().from_file(&file).unwrap().build();
+
+            let expected = songs
+                .iter()
+                .map(|song| PlaylistEntry {
+                    path: song.get_path(),
+                    title: Some(song.title()),
+                    artist: song.artist(),
+                    // only XSPF has a field for it
+                    album: song.album().filter(|_| ext == "xspf"),
+                    duration: song.duration(),
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(playlist.missing(), &expected, "{}", ext);
         }
     }
 }
@@ -0,0 +1,179 @@
+use crate::playlist::{relative_path, resolve_path, PlaylistEntry};
+use crate::song::Song;
+use color_eyre::eyre::{eyre, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Reads a PLS playlist, entries are numbered `File1`, `Title1`, `Length1` and so on.
+/// `Artist1` is not part of the format, it is only written so titles read back unchanged.
+pub fn read(path: &Path) -> Result<Vec<PlaylistEntry>> {
+    let bytes = fs::read(path)?;
+    let contents = String::from_utf8_lossy(&bytes);
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    if !contents
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .to_lowercase()
+        .starts_with("[playlist]")
+    {
+        return Err(eyre!("PLS: missing [playlist] header"));
+    }
+
+    let mut entries: BTreeMap<u32, PlaylistEntry> = BTreeMap::new();
+
+    for line in contents.lines() {
+        let (key, value) = match line.trim().split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+
+        let (field, number) = match key.find(|c: char| c.is_ascii_digit()) {
+            Some(i) => match key[i..].parse::<u32>() {
+                Ok(number) => (&key[..i], number),
+                Err(_e) => continue,
+            },
+            None => continue,
+        };
+
+        let entry = entries
+            .entry(number)
+            .or_insert_with(|| PlaylistEntry::new(PathBuf::new()));
+
+        match field {
+            "file" => entry.path = resolve_path(dir, value),
+            "title" => entry.title = Some(value.to_string()),
+            "artist" => entry.artist = Some(value.to_string()),
+            "length" => {
+                entry.duration = value
+                    .parse::<i64>()
+                    .ok()
+                    .and_then(|d| u32::try_from(d).ok())
+            }
+            _ => {}
+        }
+    }
+
+    Ok(entries
+        .into_values()
+        .filter(|entry| !entry.path.as_os_str().is_empty())
+        .map(|mut entry| {
+            entry.strip_artist();
+            entry
+        })
+        .collect())
+}
+
+pub fn write(path: &Path, songs: &[Song]) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut contents = String::from("[playlist]\n");
+
+    for (i, song) in songs.iter().enumerate() {
+        let number = i + 1;
+
+        let artist = song.artist().filter(|artist| !artist.is_empty());
+
+        let title = match &artist {
+            Some(artist) => format!("{} - {}", artist, song.title()),
+            None => song.title(),
+        };
+
+        let length = match song.duration() {
+            Some(duration) => duration as i64,
+            None => -1,
+        };
+
+        contents.push_str(&format!(
+            "File{}={}\n",
+            number,
+            relative_path(dir, &song.get_path()).display()
+        ));
+        contents.push_str(&format!("Title{}={}\n", number, title));
+
+        if let Some(artist) = artist {
+            contents.push_str(&format!("Artist{}={}\n", number, artist));
+        }
+
+        contents.push_str(&format!("Length{}={}\n", number, length));
+    }
+
+    contents.push_str(&format!("NumberOfEntries={}\n", songs.len()));
+    contents.push_str("Version=2\n");
+
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::tests::fixture;
+    use crate::song::SongBuilder;
+
+    #[test]
+    fn reads_fixture() {
+        let entries = read(&fixture("playlist.pls")).unwrap();
+        let dir = fixture("playlist.pls").parent().unwrap().to_path_buf();
+
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(
+            entries[0].path,
+            dir.join("Boards of Canada/Music Has the Right to Children/01 Wildlife Analysis.mp3")
+        );
+        assert_eq!(entries[0].title.as_deref(), Some("Wildlife Analysis"));
+        assert_eq!(entries[0].artist.as_deref(), Some("Boards of Canada"));
+        assert_eq!(entries[0].duration, Some(77));
+
+        assert_eq!(
+            entries[1].path,
+            PathBuf::from("/music/Sigur Rós/Takk.../02 Glósóli.flac")
+        );
+        // without an artist of its own the title is kept whole
+        assert_eq!(entries[1].title.as_deref(), Some("Sigur Rós - Glósóli"));
+        assert_eq!(entries[1].artist, None);
+
+        assert_eq!(entries[2].title, None);
+        assert_eq!(entries[2].duration, None);
+    }
+
+    #[test]
+    fn writes_the_artist_next_to_the_title() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("playlist.pls");
+
+        let songs = vec![
+            SongBuilder::new()
+                .title(String::from("Wildlife Analysis"))
+                .artist(Some(String::from("Boards of Canada")))
+                .duration(Some(77))
+                .path(dir.path().join("01 Wildlife Analysis.mp3"))
+                .build(),
+            SongBuilder::new()
+                .title(String::from("Intro - Live"))
+                .artist(None)
+                .duration(None)
+                .path(PathBuf::from("/music/Intro - Live.flac"))
+                .build(),
+        ];
+
+        write(&path, &songs).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[playlist]\n\
+             File1=01 Wildlife Analysis.mp3\n\
+             Title1=Boards of Canada - Wildlife Analysis\n\
+             Artist1=Boards of Canada\n\
+             Length1=77\n\
+             File2=/music/Intro - Live.flac\n\
+             Title2=Intro - Live\n\
+             Length2=-1\n\
+             NumberOfEntries=2\n\
+             Version=2\n"
+        );
+    }
+}
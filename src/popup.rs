@@ -0,0 +1,206 @@
+use crate::search::Search;
+use crate::App;
+use color_eyre::eyre::Result;
+use ratatui::{prelude::*, widgets::*};
+
+#[derive(PartialEq)]
+pub enum PopupKind {
+    ADDTOPLAYLIST,
+    NEWPLAYLIST,
+    RENAMEPLAYLIST,
+    SEEK,
+    SEARCH,
+}
+
+pub struct Popup {
+    pub kind: PopupKind,
+    pub input: String,
+    pub state: ListState,
+    pub error: Option<String>,
+    pub search: Option<Search>,
+}
+
+impl Popup {
+    pub fn new(kind: PopupKind) -> Self {
+        Popup {
+            kind,
+            input: String::new(),
+            state: ListState::default().with_selected(Some(0)),
+            error: None,
+            search: None,
+        }
+    }
+
+    pub fn with_input(mut self, input: String) -> Self {
+        self.input = input;
+        self
+    }
+
+    pub fn with_search(mut self, search: Search) -> Self {
+        self.search = Some(search);
+        self
+    }
+
+    /// Searches again with the new input, starting from the best match
+    pub fn update_search(&mut self) {
+        if let Some(search) = self.search.as_mut() {
+            search.update(&self.input);
+            self.state.select(Some(0));
+        }
+    }
+
+    pub fn next(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) if i + 1 < len => i + 1,
+            _ => 0,
+        };
+
+        self.state.select(Some(i));
+    }
+
+    pub fn previous(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) if i > 0 => i - 1,
+            _ => len - 1,
+        };
+
+        self.state.select(Some(i));
+    }
+}
+
+pub fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+pub fn popup(app: &mut App, f: &mut Frame) -> Result<()> {
+    let names = app
+        .playlists_state
+        .editable()
+        .iter()
+        .filter_map(|i| app.playlists_state.playlist(*i))
+        .map(|playlist| format!("{} ({})", playlist.name(), playlist.length()))
+        .collect::<Vec<String>>();
+
+    let popup = match app.popup.as_mut() {
+        Some(popup) => popup,
+        None => return Ok(()),
+    };
+
+    let title = match popup.kind {
+        PopupKind::ADDTOPLAYLIST => " Add to playlist ",
+        PopupKind::NEWPLAYLIST => " New playlist ",
+        PopupKind::RENAMEPLAYLIST => " Rename playlist ",
+        PopupKind::SEEK => " Seek to (mm:ss) ",
+        PopupKind::SEARCH => " Search (enter: queue, ctrl-p: play next, ctrl-o: open folder) ",
+    };
+
+    let area = match popup.kind {
+        PopupKind::SEARCH => centered_rect(100, 24, f.size()),
+        _ => centered_rect(50, 12, f.size()),
+    };
+    let block = Block::new().borders(Borders::ALL).title(title);
+
+    let layout = Layout::default()
+        .constraints(vec![Constraint::Min(1), Constraint::Max(1)])
+        .split(block.inner(area));
+
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+
+    match popup.kind {
+        PopupKind::ADDTOPLAYLIST => {
+            let mut items = vec![String::from("<New playlist>")];
+            items.extend(names);
+
+            f.render_stateful_widget(
+                List::new(items)
+                    .highlight_symbol("$ ")
+                    .highlight_style(Style::default().bg(Color::DarkGray)),
+                layout[0],
+                &mut popup.state,
+            );
+        }
+        PopupKind::NEWPLAYLIST | PopupKind::RENAMEPLAYLIST | PopupKind::SEEK => {
+            f.render_widget(Paragraph::new(format!("{}_", popup.input)), layout[0]);
+        }
+        PopupKind::SEARCH => {
+            let search = match &popup.search {
+                Some(search) => search,
+                None => return Ok(()),
+            };
+
+            let parts = Layout::default()
+                .constraints(vec![Constraint::Length(2), Constraint::Min(1)])
+                .split(layout[0]);
+
+            f.render_widget(
+                Paragraph::new(format!("/{}_", popup.input))
+                    .block(Block::new().borders(Borders::BOTTOM)),
+                parts[0],
+            );
+
+            let items = search
+                .results()
+                .map(|song| {
+                    let mut spans = vec![Span::styled(song.title(), Style::default().bold())];
+
+                    for tag in [song.artist(), song.album()].into_iter().flatten() {
+                        if !tag.is_empty() {
+                            spans.push(Span::raw(format!(" - {}", tag)));
+                        }
+                    }
+
+                    ListItem::new(Line::from(spans))
+                })
+                .collect::<Vec<ListItem>>();
+
+            f.render_stateful_widget(
+                List::new(items)
+                    .highlight_symbol("$ ")
+                    .highlight_style(Style::default().bg(Color::DarkGray)),
+                parts[1],
+                &mut popup.state,
+            );
+
+            if popup.error.is_none() {
+                let count = match search.matches() {
+                    0 => String::new(),
+                    matches if matches > search.len() => {
+                        format!("{} matches, showing the best {}", matches, search.len())
+                    }
+                    matches => format!("{} matches", matches),
+                };
+
+                f.render_widget(
+                    Paragraph::new(count).style(Style::default().fg(Color::DarkGray)),
+                    layout[1],
+                );
+            }
+        }
+    }
+
+    if let Some(error) = &popup.error {
+        f.render_widget(
+            Paragraph::new(error.as_str()).style(Style::default().fg(Color::Red)),
+            layout[1],
+        );
+    }
+
+    Ok(())
+}
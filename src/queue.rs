@@ -0,0 +1,485 @@
+use crate::song::Song;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepeatMode {
+    OFF,
+    ONE,
+    ALL,
+}
+
+impl RepeatMode {
+    pub fn next(self) -> RepeatMode {
+        match self {
+            RepeatMode::OFF => RepeatMode::ALL,
+            RepeatMode::ALL => RepeatMode::ONE,
+            RepeatMode::ONE => RepeatMode::OFF,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Queue {
+    songs: Vec<Song>,
+    current: Option<usize>,
+    // the order songs are played in while shuffled, indices into songs
+    order: Option<Vec<usize>>,
+    repeat: RepeatMode,
+    // the song that has been decoded ahead to follow the current one
+    preloaded: Option<usize>,
+    // the player still holds a song that is no longer the one to preload
+    reload: bool,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Queue {
+            songs: Vec::new(),
+            current: None,
+            order: None,
+            repeat: RepeatMode::OFF,
+            preloaded: None,
+            reload: false,
+        }
+    }
+
+    pub fn songs(&self) -> &Vec<Song> {
+        &self.songs
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current_song(&self) -> Option<&Song> {
+        self.songs.get(self.current?)
+    }
+
+    pub fn shuffled(&self) -> bool {
+        self.order.is_some()
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    /// The playing song stays first, everything else is shuffled after it.
+    /// Turning shuffle off goes back to the order of the queue.
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        if !shuffle {
+            self.order = None;
+            return;
+        }
+
+        let mut rest = (0..self.songs.len())
+            .filter(|i| Some(*i) != self.current)
+            .collect::<Vec<usize>>();
+        rest.shuffle(&mut thread_rng());
+
+        let mut order = self.current.into_iter().collect::<Vec<usize>>();
+        order.extend(rest);
+
+        self.order = Some(order);
+    }
+
+    fn play_order(&self) -> Vec<usize> {
+        match &self.order {
+            Some(order) => order.clone(),
+            None => (0..self.songs.len()).collect(),
+        }
+    }
+
+    /// Position of the playing song in the play order
+    fn position(&self) -> Option<usize> {
+        let current = self.current?;
+
+        match &self.order {
+            Some(order) => order.iter().position(|i| *i == current),
+            None => Some(current),
+        }
+    }
+
+    /// The song after the playing one, ignoring repeat one
+    fn following(&self) -> Option<usize> {
+        let order = self.play_order();
+
+        match order.get(self.position()? + 1) {
+            Some(i) => Some(*i),
+            None if self.repeat == RepeatMode::ALL => order.first().copied(),
+            None => None,
+        }
+    }
+
+    /// The song that plays once the current one finishes on its own
+    pub fn upcoming(&self) -> Option<usize> {
+        match self.repeat {
+            RepeatMode::ONE => self.current,
+            _ => self.following(),
+        }
+    }
+
+    pub fn set_preloaded(&mut self, preloaded: Option<usize>) {
+        self.preloaded = preloaded;
+        self.reload = false;
+    }
+
+    /// Hands the upcoming song over again, even when there is none left to play
+    pub fn reload_preloaded(&mut self) {
+        self.preloaded = None;
+        self.reload = true;
+    }
+
+    /// The preloaded song is not the upcoming one anymore
+    pub fn needs_preload(&self) -> bool {
+        self.reload || self.upcoming() != self.preloaded
+    }
+
+    /// The preloaded song took over, returns None if it was removed in the meantime
+    pub fn start_preloaded(&mut self) -> Option<usize> {
+        let preloaded = self.preloaded.take()?;
+        self.current = Some(preloaded);
+
+        Some(preloaded)
+    }
+
+    /// Applies a change of indices in songs to current, preloaded and the shuffled order
+    fn remap(&mut self, f: impl Fn(usize) -> usize) {
+        self.current = self.current.map(&f);
+        self.preloaded = self.preloaded.map(&f);
+
+        if let Some(order) = self.order.as_mut() {
+            order.iter_mut().for_each(|i| *i = f(*i));
+        }
+    }
+
+    pub fn push(&mut self, song: Song) {
+        self.songs.push(song);
+        let index = self.songs.len() - 1;
+
+        // new songs land somewhere among the songs that have not played yet
+        let start = self.position().map_or(0, |position| position + 1);
+        if let Some(order) = self.order.as_mut() {
+            let at = thread_rng().gen_range(start..=order.len());
+            order.insert(at, index);
+        }
+
+        if self.current.is_none() {
+            self.current = Some(index);
+        }
+    }
+
+    /// Inserts the song right after the one that is playing
+    pub fn play_next(&mut self, song: Song) {
+        let current = match self.current {
+            Some(current) => current,
+            None => return self.push(song),
+        };
+
+        let position = self.position();
+        let index = current + 1;
+
+        self.songs.insert(index, song);
+        self.remap(|i| if i >= index { i + 1 } else { i });
+
+        if let (Some(order), Some(position)) = (self.order.as_mut(), position) {
+            order.insert(position + 1, index);
+        }
+    }
+
+    /// Returns true if the removed song was the one playing
+    pub fn remove(&mut self, index: usize) -> bool {
+        if index >= self.songs.len() {
+            return false;
+        }
+
+        let playing = self.current == Some(index);
+        let following = self.following().filter(|i| *i != index);
+
+        self.songs.remove(index);
+
+        if let Some(order) = self.order.as_mut() {
+            order.retain(|i| *i != index);
+        }
+
+        if self.preloaded == Some(index) {
+            self.reload_preloaded();
+        }
+
+        if playing {
+            self.current = following;
+        }
+
+        self.remap(|i| if i > index { i - 1 } else { i });
+
+        playing
+    }
+
+    pub fn move_song(&mut self, from: usize, to: usize) {
+        if from >= self.songs.len() || to >= self.songs.len() || from == to {
+            return;
+        }
+
+        let song = self.songs.remove(from);
+        self.songs.insert(to, song);
+
+        self.remap(|i| match i {
+            i if i == from => to,
+            i if from < i && i <= to => i - 1,
+            i if to <= i && i < from => i + 1,
+            i => i,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.songs.clear();
+        self.current = None;
+        self.reload_preloaded();
+
+        if self.order.is_some() {
+            self.order = Some(Vec::new());
+        }
+    }
+
+    /// Called when a song finishes, returns None once the end of the queue is reached
+    pub fn advance(&mut self) -> Option<&Song> {
+        if self.repeat != RepeatMode::ONE {
+            self.current = self.following();
+        }
+
+        self.current_song()
+    }
+
+    /// Moves on to the next song even when repeating one
+    pub fn skip(&mut self) -> Option<&Song> {
+        self.current = self.following();
+
+        self.current_song()
+    }
+
+    /// Stops at the current song, the next push starts playing again
+    pub fn stop(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::song::SongBuilder;
+    use std::path::PathBuf;
+
+    fn song(title: &str) -> Song {
+        SongBuilder::new()
+            .title(String::from(title))
+            .path(PathBuf::from(format!("{}.mp3", title)))
+            .build()
+    }
+
+    fn queue(len: usize) -> Queue {
+        let mut queue = Queue::new();
+
+        for i in 0..len {
+            queue.push(song(&i.to_string()));
+        }
+
+        queue
+    }
+
+    fn titles(queue: &Queue) -> Vec<String> {
+        queue.songs().iter().map(|song| song.title()).collect()
+    }
+
+    /// Titles in the order they play, starting at the current song
+    fn played(queue: &mut Queue) -> Vec<String> {
+        let mut played = queue
+            .current_song()
+            .map(|song| song.title())
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        while let Some(song) = queue.advance() {
+            played.push(song.title());
+        }
+
+        played
+    }
+
+    #[test]
+    fn repeat_modes() {
+        let mut queue = queue(3);
+        assert_eq!(played(&mut queue), vec!["0", "1", "2"]);
+        assert_eq!(queue.current(), None);
+
+        let mut queue = self::queue(3);
+        queue.set_repeat(RepeatMode::ALL);
+        queue.advance();
+        queue.advance();
+        assert_eq!(queue.upcoming(), Some(0));
+        assert_eq!(
+            queue.advance().map(|song| song.title()).as_deref(),
+            Some("0")
+        );
+
+        queue.set_repeat(RepeatMode::ONE);
+        assert_eq!(queue.upcoming(), Some(0));
+        assert_eq!(
+            queue.advance().map(|song| song.title()).as_deref(),
+            Some("0")
+        );
+        assert_eq!(queue.skip().map(|song| song.title()).as_deref(), Some("1"));
+
+        assert_eq!(RepeatMode::OFF.next().next().next(), RepeatMode::OFF);
+    }
+
+    #[test]
+    fn shuffle_plays_everything_once_starting_with_the_current_song() {
+        let mut queue = queue(20);
+        queue.advance();
+        queue.set_shuffle(true);
+
+        let mut order = played(&mut queue);
+        assert_eq!(order[0], "1");
+        assert_eq!(order.len(), 20);
+
+        order.sort_by_key(|title| title.parse::<usize>().unwrap());
+        assert_eq!(order, (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn turning_shuffle_off_goes_back_to_the_queue_order() {
+        let mut queue = queue(10);
+        queue.set_shuffle(true);
+        queue.advance();
+        let current = queue.current().unwrap();
+
+        queue.set_shuffle(false);
+
+        assert_eq!(queue.current(), Some(current));
+        assert_eq!(queue.upcoming(), Some(current + 1).filter(|i| *i < 10));
+    }
+
+    #[test]
+    fn songs_pushed_while_shuffled_have_not_played_yet() {
+        let mut queue = queue(5);
+        queue.set_shuffle(true);
+        queue.advance();
+        queue.advance();
+
+        queue.push(song("new"));
+
+        let order = played(&mut queue);
+        assert_eq!(order.len(), 4);
+        assert!(order[1..].contains(&String::from("new")));
+    }
+
+    #[test]
+    fn play_next_follows_the_current_song() {
+        let mut queue = queue(4);
+        queue.advance();
+        queue.set_shuffle(true);
+        queue.set_preloaded(queue.upcoming());
+        let preloaded = titles(&queue)[queue.preloaded.unwrap()].clone();
+
+        queue.play_next(song("next"));
+
+        assert_eq!(titles(&queue)[2], "next");
+        assert_eq!(queue.current(), Some(1));
+        assert_eq!(queue.upcoming(), Some(2));
+        assert_eq!(titles(&queue)[queue.preloaded.unwrap()], preloaded);
+        assert!(queue.needs_preload());
+    }
+
+    #[test]
+    fn moving_songs_keeps_current_and_preloaded() {
+        let mut queue = queue(5);
+        queue.advance();
+        queue.set_preloaded(queue.upcoming());
+
+        // 0 1 2 3 4 -> 1 2 0 3 4
+        queue.move_song(0, 2);
+        assert_eq!(titles(&queue), vec!["1", "2", "0", "3", "4"]);
+        assert_eq!(queue.current_song().unwrap().title(), "1");
+        assert_eq!(queue.preloaded, Some(1));
+        assert!(!queue.needs_preload());
+
+        // 1 2 0 3 4 -> 1 4 2 0 3
+        queue.move_song(4, 1);
+        assert_eq!(titles(&queue), vec!["1", "4", "2", "0", "3"]);
+        assert_eq!(queue.current(), Some(0));
+        assert_eq!(queue.preloaded, Some(2));
+        assert!(queue.needs_preload());
+
+        queue.move_song(0, 9);
+        assert_eq!(titles(&queue), vec!["1", "4", "2", "0", "3"]);
+    }
+
+    #[test]
+    fn removing_songs() {
+        let mut queue = queue(5);
+        queue.advance();
+        queue.advance();
+        queue.set_preloaded(queue.upcoming());
+
+        // before the current song, everything shifts down
+        assert!(!queue.remove(0));
+        assert_eq!(queue.current_song().unwrap().title(), "2");
+        assert_eq!(queue.preloaded, Some(2));
+        assert!(!queue.needs_preload());
+
+        // the playing song hands over to the one after it
+        assert!(queue.remove(1));
+        assert_eq!(titles(&queue), vec!["1", "3", "4"]);
+        assert_eq!(queue.current_song().unwrap().title(), "3");
+
+        assert!(!queue.remove(7));
+        assert_eq!(titles(&queue).len(), 3);
+    }
+
+    #[test]
+    fn removing_the_last_preloaded_song_reloads() {
+        let mut queue = queue(2);
+        queue.set_preloaded(queue.upcoming());
+        assert!(!queue.needs_preload());
+
+        queue.remove(1);
+
+        assert_eq!(queue.upcoming(), None);
+        assert_eq!(queue.preloaded, None);
+        assert!(queue.needs_preload());
+
+        queue.set_preloaded(queue.upcoming());
+        assert!(!queue.needs_preload());
+    }
+
+    #[test]
+    fn removing_the_playing_song_while_shuffled() {
+        let mut queue = queue(6);
+        queue.set_shuffle(true);
+        let following = queue.upcoming().map(|i| queue.songs()[i].title());
+
+        let current = queue.current().unwrap();
+        assert!(queue.remove(current));
+
+        assert_eq!(queue.current_song().map(|song| song.title()), following);
+        assert_eq!(played(&mut queue).len(), 5);
+    }
+
+    #[test]
+    fn clearing_forgets_everything() {
+        let mut queue = queue(3);
+        queue.set_preloaded(queue.upcoming());
+
+        queue.clear();
+
+        assert!(queue.songs().is_empty());
+        assert_eq!(queue.current(), None);
+        assert!(queue.needs_preload());
+
+        queue.push(song("again"));
+        assert_eq!(queue.current_song().unwrap().title(), "again");
+    }
+}
@@ -0,0 +1,482 @@
+use crate::format::{is_ogg, AudioFormat};
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Opus R128 gains are relative to -23 LUFS, ReplayGain to -18 LUFS
+const R128_TO_REPLAYGAIN: f32 = 5.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GainMode {
+    OFF,
+    TRACK,
+    ALBUM,
+}
+
+impl GainMode {
+    pub fn next(self) -> GainMode {
+        match self {
+            GainMode::OFF => GainMode::TRACK,
+            GainMode::TRACK => GainMode::ALBUM,
+            GainMode::ALBUM => GainMode::OFF,
+        }
+    }
+}
+
+/// Gains are in dB, peaks are linear with 1.0 as full scale
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Files without tags, or in a format without tag support, get no gain
+    pub fn read(path: &Path) -> ReplayGain {
+        match AudioFormat::detect(path) {
+            _ if is_ogg(path) => read_ogg(path),
+            Some(format @ AudioFormat::MP3)
+            | Some(format @ AudioFormat::AAC)
+            | Some(format @ AudioFormat::WAV)
+            | Some(format @ AudioFormat::AIFF) => read_id3(path, format),
+            Some(AudioFormat::FLAC) => read_flac(path),
+            Some(AudioFormat::VORBIS) | Some(AudioFormat::OPUS) => read_ogg(path),
+            Some(AudioFormat::MP4) => read_mp4(path),
+            None => None,
+        }
+        .unwrap_or_default()
+    }
+
+    /// Linear factor to play the song at, never pushing its peak past full scale.
+    /// `preamp` in dB is used for songs without tags.
+    pub fn factor(&self, mode: GainMode, preamp: f32) -> f32 {
+        let (gain, peak) = match mode {
+            GainMode::OFF => return 1.0,
+            GainMode::TRACK => (
+                self.track_gain.or(self.album_gain),
+                self.track_peak.or(self.album_peak),
+            ),
+            GainMode::ALBUM => (
+                self.album_gain.or(self.track_gain),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+
+        let factor = 10f32.powf(gain.unwrap_or(preamp) / 20.0);
+
+        match peak {
+            Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
+
+    /// Writes the tags into the file, replacing any that were there
+    pub fn write(&self, path: &Path) -> Result<()> {
+        match AudioFormat::detect(path) {
+            _ if is_ogg(path) => Err(eyre!("Writing tags to Ogg files is not supported")),
+            Some(format @ AudioFormat::MP3)
+            | Some(format @ AudioFormat::AAC)
+            | Some(format @ AudioFormat::WAV)
+            | Some(format @ AudioFormat::AIFF) => self.write_id3(path, format),
+            Some(AudioFormat::FLAC) => self.write_flac(path),
+            Some(AudioFormat::MP4) => self.write_mp4(path),
+            Some(AudioFormat::VORBIS) | Some(AudioFormat::OPUS) => {
+                Err(eyre!("Writing tags to Ogg files is not supported"))
+            }
+            None => Err(eyre!("Unsupported format")),
+        }
+    }
+
+    fn values(&self) -> [(&'static str, Option<String>); 4] {
+        let gain = |gain: Option<f32>| gain.map(|gain| format!("{:.2} dB", gain));
+        let peak = |peak: Option<f32>| peak.map(|peak| format!("{:.6}", peak));
+
+        [
+            ("REPLAYGAIN_TRACK_GAIN", gain(self.track_gain)),
+            ("REPLAYGAIN_TRACK_PEAK", peak(self.track_peak)),
+            ("REPLAYGAIN_ALBUM_GAIN", gain(self.album_gain)),
+            ("REPLAYGAIN_ALBUM_PEAK", peak(self.album_peak)),
+        ]
+    }
+
+    fn write_id3(&self, path: &Path, format: AudioFormat) -> Result<()> {
+        use id3::TagLike;
+
+        let mut tag = read_id3_tag(path, format).unwrap_or_default();
+
+        for (key, value) in self.values() {
+            tag.remove_extended_text(Some(key), None);
+            tag.remove_extended_text(Some(&key.to_lowercase()), None);
+
+            if let Some(value) = value {
+                tag.add_frame(id3::frame::ExtendedText {
+                    description: String::from(key),
+                    value,
+                });
+            }
+        }
+
+        let version = id3::Version::Id3v24;
+
+        match format {
+            AudioFormat::WAV => tag.write_to_wav_path(path, version)?,
+            AudioFormat::AIFF => tag.write_to_aiff_path(path, version)?,
+            _ => tag.write_to_path(path, version)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_flac(&self, path: &Path) -> Result<()> {
+        let mut tag = metaflac::Tag::read_from_path(path)?;
+
+        for (key, value) in self.values() {
+            tag.remove_vorbis(key);
+
+            if let Some(value) = value {
+                tag.set_vorbis(key, vec![value]);
+            }
+        }
+
+        tag.save()?;
+        Ok(())
+    }
+
+    fn write_mp4(&self, path: &Path) -> Result<()> {
+        let mut tag = mp4ameta::Tag::read_from_path(path)?;
+
+        for (key, value) in self.values() {
+            let ident = mp4ameta::FreeformIdent::new("com.apple.iTunes", key);
+            let lowercase = key.to_lowercase();
+
+            tag.remove_data_of(&ident);
+            tag.remove_data_of(&mp4ameta::FreeformIdent::new(
+                "com.apple.iTunes",
+                &lowercase,
+            ));
+
+            if let Some(value) = value {
+                tag.set_data(ident, mp4ameta::Data::Utf8(value));
+            }
+        }
+
+        tag.write_to_path(path)?;
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let key = key.to_uppercase();
+
+        match key.as_str() {
+            "REPLAYGAIN_TRACK_GAIN" => self.track_gain = parse_number(value),
+            "REPLAYGAIN_TRACK_PEAK" => self.track_peak = parse_number(value),
+            "REPLAYGAIN_ALBUM_GAIN" => self.album_gain = parse_number(value),
+            "REPLAYGAIN_ALBUM_PEAK" => self.album_peak = parse_number(value),
+            "R128_TRACK_GAIN" => self.track_gain = parse_r128(value),
+            "R128_ALBUM_GAIN" => self.album_gain = parse_r128(value),
+            _ => {}
+        }
+    }
+}
+
+/// Values look like `-6.54 dB` or `0.988553`
+fn parse_number(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .trim_end_matches("dB")
+        .trim_end_matches("db")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// R128 gains are Q7.8 fixed point integers
+fn parse_r128(value: &str) -> Option<f32> {
+    let value = value.trim().parse::<i16>().ok()?;
+
+    Some(value as f32 / 256.0 + R128_TO_REPLAYGAIN)
+}
+
+/// WAV and AIFF keep their ID3 tag in a chunk of its own
+pub fn read_id3_tag(path: &Path, format: AudioFormat) -> Option<id3::Tag> {
+    match format {
+        AudioFormat::WAV => id3::Tag::read_from_wav_path(path),
+        AudioFormat::AIFF => id3::Tag::read_from_aiff_path(path),
+        _ => id3::Tag::read_from_path(path),
+    }
+    .ok()
+}
+
+fn read_id3(path: &Path, format: AudioFormat) -> Option<ReplayGain> {
+    let tag = read_id3_tag(path, format)?;
+    let mut gain = ReplayGain::default();
+
+    for text in tag.extended_texts() {
+        gain.set(&text.description, &text.value);
+    }
+
+    Some(gain)
+}
+
+fn read_flac(path: &Path) -> Option<ReplayGain> {
+    let tag = metaflac::Tag::read_from_path(path).ok()?;
+    let mut gain = ReplayGain::default();
+
+    for (key, values) in &tag.vorbis_comments()?.comments {
+        if let Some(value) = values.first() {
+            gain.set(key, value);
+        }
+    }
+
+    Some(gain)
+}
+
+fn read_mp4(path: &Path) -> Option<ReplayGain> {
+    let tag = mp4ameta::Tag::read_from_path(path).ok()?;
+    let mut gain = ReplayGain::default();
+
+    for key in [
+        "replaygain_track_gain",
+        "replaygain_track_peak",
+        "replaygain_album_gain",
+        "replaygain_album_peak",
+    ] {
+        let ident = mp4ameta::FreeformIdent::new("com.apple.iTunes", key);
+
+        let value = tag.strings_of(&ident).next().map(String::from);
+
+        if let Some(value) = value {
+            gain.set(key, &value);
+        }
+    }
+
+    Some(gain)
+}
+
+fn read_ogg(path: &Path) -> Option<ReplayGain> {
+    let mut gain = ReplayGain::default();
+
+    for (key, value) in ogg_comments(path)? {
+        gain.set(&key, &value);
+    }
+
+    Some(gain)
+}
+
+/// The comment header is the second packet of Vorbis, Opus and FLAC streams in Ogg
+pub fn ogg_comments(path: &Path) -> Option<Vec<(String, String)>> {
+    let mut reader = ogg::PacketReader::new(BufReader::new(File::open(path).ok()?));
+
+    reader.read_packet().ok()??;
+    let packet = reader.read_packet().ok()??;
+    let data = &packet.data;
+
+    let comments = if let Some(comments) = data.strip_prefix(b"\x03vorbis") {
+        comments
+    } else if let Some(comments) = data.strip_prefix(b"OpusTags") {
+        comments
+    } else if data.first()? & 0x7F == 4 {
+        // a FLAC VORBIS_COMMENT metadata block, after its type and 24 bit length
+        data.get(4..)?
+    } else {
+        return None;
+    };
+
+    let comments = vorbis_comments(comments)?
+        .into_iter()
+        .filter_map(|comment| {
+            let (key, value) = comment.split_once('=')?;
+            Some((String::from(key), String::from(value)))
+        })
+        .collect();
+
+    Some(comments)
+}
+
+/// A vendor string followed by a list of `KEY=value` strings, all prefixed by their length
+fn vorbis_comments(data: &[u8]) -> Option<Vec<String>> {
+    let mut position = 0;
+
+    let read_u32 = |position: &mut usize| -> Option<u32> {
+        let bytes = data.get(*position..*position + 4)?;
+        *position += 4;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    };
+
+    let vendor = read_u32(&mut position)? as usize;
+    position += vendor;
+
+    let count = read_u32(&mut position)?;
+    let mut comments = Vec::new();
+
+    for _ in 0..count {
+        let len = read_u32(&mut position)? as usize;
+        let comment = data.get(position..position + len)?;
+        position += len;
+
+        comments.push(String::from_utf8_lossy(comment).to_string());
+    }
+
+    Some(comments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn parses_gains_and_peaks() {
+        assert_eq!(parse_number("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_number(" +2.10 db "), Some(2.1));
+        assert_eq!(parse_number("0.988553"), Some(0.988553));
+        assert_eq!(parse_number("loud"), None);
+    }
+
+    #[test]
+    fn r128_gains_are_q7_8_relative_to_minus_23_lufs() {
+        // -23 LUFS is -5 dB below the ReplayGain reference
+        assert_eq!(parse_r128("-1280"), Some(0.0));
+        assert_eq!(parse_r128("0"), Some(5.0));
+        assert_eq!(parse_r128(" 384 "), Some(6.5));
+        assert_eq!(parse_r128("-3.5"), None);
+        assert_eq!(parse_r128("40000"), None);
+    }
+
+    #[test]
+    fn keys_ignore_case() {
+        let mut gain = ReplayGain::default();
+
+        gain.set("replaygain_track_gain", "-3.00 dB");
+        gain.set("REPLAYGAIN_TRACK_PEAK", "0.5");
+        gain.set("R128_ALBUM_GAIN", "-768");
+        gain.set("COMMENT", "-1 dB");
+
+        assert_eq!(
+            gain,
+            ReplayGain {
+                track_gain: Some(-3.0),
+                track_peak: Some(0.5),
+                album_gain: Some(2.0),
+                album_peak: None,
+            }
+        );
+    }
+
+    #[test]
+    fn gain_factors() {
+        let gain = ReplayGain {
+            track_gain: Some(-6.0),
+            track_peak: Some(0.9),
+            album_gain: Some(6.0),
+            album_peak: Some(0.9),
+        };
+
+        assert_eq!(gain.factor(GainMode::OFF, 0.0), 1.0);
+        assert!(close(gain.factor(GainMode::TRACK, 0.0), 0.501187));
+
+        // +6 dB would clip the 0.9 peak, it is only raised to full scale
+        assert!(close(gain.factor(GainMode::ALBUM, 0.0), 1.0 / 0.9));
+    }
+
+    #[test]
+    fn missing_gains_fall_back() {
+        let track = ReplayGain {
+            track_gain: Some(-6.0),
+            ..ReplayGain::default()
+        };
+        assert!(close(track.factor(GainMode::ALBUM, 0.0), 0.501187));
+
+        let album = ReplayGain {
+            album_gain: Some(-6.0),
+            ..ReplayGain::default()
+        };
+        assert!(close(album.factor(GainMode::TRACK, 0.0), 0.501187));
+
+        // untagged songs play at the preamp
+        let untagged = ReplayGain::default();
+        assert!(close(untagged.factor(GainMode::TRACK, -6.0), 0.501187));
+        assert_eq!(untagged.factor(GainMode::ALBUM, 0.0), 1.0);
+    }
+
+    #[test]
+    fn reads_vorbis_comments() {
+        let mut data = Vec::new();
+
+        for bytes in [&b"vendor"[..], b"R128_TRACK_GAIN=-256", b"TITLE=Glosoli"] {
+            data.extend((bytes.len() as u32).to_le_bytes());
+            data.extend(bytes);
+
+            // the number of comments follows the vendor string
+            if bytes == b"vendor" {
+                data.extend(2u32.to_le_bytes());
+            }
+        }
+
+        assert_eq!(
+            vorbis_comments(&data),
+            Some(vec![
+                String::from("R128_TRACK_GAIN=-256"),
+                String::from("TITLE=Glosoli")
+            ])
+        );
+
+        assert_eq!(vorbis_comments(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn reads_r128_from_opus_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.opus");
+        let tags = ["R128_TRACK_GAIN=-512", "R128_ALBUM_GAIN=256"];
+        std::fs::write(&path, crate::opus::tests::encode(2, 5, &tags)).unwrap();
+
+        assert_eq!(
+            ReplayGain::read(&path),
+            ReplayGain {
+                track_gain: Some(3.0),
+                album_gain: Some(6.0),
+                ..ReplayGain::default()
+            }
+        );
+        assert!(ReplayGain::default().write(&path).is_err());
+    }
+
+    #[test]
+    fn reads_comments_from_ogg_flac() {
+        use ogg::{PacketWriteEndInfo, PacketWriter};
+
+        let mut head = b"\x7fFLAC\x01\x00\x00\x01fLaC\x00\x00\x00\x22".to_vec();
+        head.extend([0; 34]);
+
+        let comment = b"REPLAYGAIN_TRACK_GAIN=-4.50 dB";
+        let mut block = vec![0x84, 0, 0, (4 + 4 + 4 + comment.len()) as u8];
+        block.extend(0u32.to_le_bytes());
+        block.extend(1u32.to_le_bytes());
+        block.extend((comment.len() as u32).to_le_bytes());
+        block.extend(comment);
+
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        for packet in [head, block] {
+            writer
+                .write_packet(packet.into_boxed_slice(), 1, PacketWriteEndInfo::EndPage, 0)
+                .unwrap();
+        }
+        drop(writer);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.oga");
+        std::fs::write(&path, data).unwrap();
+
+        assert_eq!(AudioFormat::detect(&path), Some(AudioFormat::FLAC));
+        assert_eq!(ReplayGain::read(&path).track_gain, Some(-4.5));
+    }
+}
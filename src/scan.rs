@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+pub enum ScanEvent {
+    STARTED(usize),
+    SCANNED(PathBuf),
+    FINISHED,
+    ERROR(String),
+}
+
+/// Progress of a job running over a list of files in the background
+pub struct Scan {
+    rx: Receiver<ScanEvent>,
+    pub total: usize,
+    pub done: usize,
+    pub current: Option<PathBuf>,
+    pub finished: bool,
+}
+
+/// Runs `job` on its own thread, it reports through the sender and finishes when it returns
+pub fn spawn<F>(job: F) -> Scan
+where
+    F: FnOnce(&Sender<ScanEvent>) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        job(&tx);
+        let _ = tx.send(ScanEvent::FINISHED);
+    });
+
+    Scan {
+        rx,
+        total: 0,
+        done: 0,
+        current: None,
+        finished: false,
+    }
+}
+
+impl Scan {
+    /// Applies the events sent so far and returns the errors among them
+    pub fn update(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        while let Ok(event) = self.rx.try_recv() {
+            match event {
+                ScanEvent::STARTED(total) => self.total = total,
+                ScanEvent::SCANNED(path) => {
+                    self.done += 1;
+                    self.current = Some(path);
+                }
+                ScanEvent::FINISHED => self.finished = true,
+                ScanEvent::ERROR(error) => errors.push(error),
+            }
+        }
+
+        errors
+    }
+
+    pub fn ratio(&self) -> f64 {
+        match self.total {
+            0 => 0.0,
+            total => (self.done as f64 / total as f64).clamp(0.0, 1.0),
+        }
+    }
+}
@@ -0,0 +1,627 @@
+use crate::library;
+use crate::song::Song;
+use crate::App;
+use color_eyre::eyre::Result;
+use ratatui::{prelude::*, widgets::*};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const UNKNOWN_ARTIST: &str = "Unknown artist";
+const UNKNOWN_ALBUM: &str = "Unknown album";
+const UNKNOWN_GENRE: &str = "Unknown genre";
+
+/// What the songs are grouped by at the top of the library
+#[derive(Clone, Copy, PartialEq)]
+pub enum LibraryGroup {
+    ARTIST,
+    /// Keeps compilations together, songs without one fall back to their artist
+    ALBUMARTIST,
+    /// Every album at the top, without a group above them
+    ALBUM,
+    GENRE,
+}
+
+impl LibraryGroup {
+    pub fn next(self) -> LibraryGroup {
+        match self {
+            LibraryGroup::ALBUMARTIST => LibraryGroup::ARTIST,
+            LibraryGroup::ARTIST => LibraryGroup::ALBUM,
+            LibraryGroup::ALBUM => LibraryGroup::GENRE,
+            LibraryGroup::GENRE => LibraryGroup::ALBUMARTIST,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LibraryGroup::ARTIST => "Artists",
+            LibraryGroup::ALBUMARTIST => "Album artists",
+            LibraryGroup::ALBUM => "Albums",
+            LibraryGroup::GENRE => "Genres",
+        }
+    }
+
+    fn key(self, song: &Song) -> String {
+        let key = match self {
+            LibraryGroup::ARTIST => song.artist(),
+            LibraryGroup::ALBUMARTIST | LibraryGroup::ALBUM => {
+                song.album_artist().or(song.artist())
+            }
+            LibraryGroup::GENRE => song.genre(),
+        };
+
+        match key {
+            Some(key) if !key.is_empty() => key,
+            _ if self == LibraryGroup::GENRE => String::from(UNKNOWN_GENRE),
+            _ => String::from(UNKNOWN_ARTIST),
+        }
+    }
+}
+
+/// Albums with the same title by different artists are kept apart,
+/// songs without an album are grouped by their directory instead
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Album {
+    name: String,
+    artist: String,
+    directory: Option<PathBuf>,
+}
+
+impl Album {
+    fn of(song: &Song) -> Album {
+        let directory = match song.album() {
+            Some(album) if !album.is_empty() => None,
+            _ => song.get_path().parent().map(PathBuf::from),
+        };
+
+        Album {
+            name: album(song),
+            artist: LibraryGroup::ALBUMARTIST.key(song),
+            directory,
+        }
+    }
+
+    fn label(&self) -> String {
+        match &self.directory {
+            Some(directory) => match directory.file_name() {
+                Some(name) => format!("{} ({})", self.name, name.to_string_lossy()),
+                None => self.name.clone(),
+            },
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Drill down from a group to its albums to the tracks of one album.
+/// Albums listed at the top have no group above them.
+#[derive(Clone, PartialEq)]
+pub enum LibraryView {
+    GROUPS,
+    ALBUMS(String),
+    TRACKS(Option<String>, Album),
+}
+
+pub struct LibraryState {
+    group: LibraryGroup,
+    view: LibraryView,
+    /// Songs in the current view, in the order they are listed or grouped
+    songs: Vec<Song>,
+    /// Groups behind the listed items, when groups are listed
+    groups: Vec<String>,
+    /// Albums behind the listed items, when albums are listed
+    albums: Vec<Album>,
+    items: Vec<String>,
+    state: ListState,
+    /// Selection of each view above the current one, restored when going back
+    history: Vec<Option<usize>>,
+}
+
+fn album(song: &Song) -> String {
+    match song.album() {
+        Some(album) if !album.is_empty() => album,
+        _ => String::from(UNKNOWN_ALBUM),
+    }
+}
+
+/// Songs without a year, disc or track number go last
+fn compare_missing<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Every album of the songs, as old as its oldest track
+fn albums(songs: &[Song]) -> Vec<(Album, Option<i32>)> {
+    let mut albums: Vec<(Album, Option<i32>)> = Vec::new();
+    // the whole library is listed by album, looking each one up has to be quick
+    let mut indices: HashMap<Album, usize> = HashMap::new();
+
+    for song in songs {
+        let album = Album::of(song);
+
+        match indices.get(&album) {
+            Some(i) => {
+                let year = &mut albums[*i].1;
+                *year = match (*year, song.year()) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+            None => {
+                indices.insert(album.clone(), albums.len());
+                albums.push((album, song.year()));
+            }
+        }
+    }
+
+    albums
+}
+
+fn album_order(a: &Song, b: &Song) -> Ordering {
+    compare_missing(a.year(), b.year())
+        .then_with(|| album(a).cmp(&album(b)))
+        .then_with(|| {
+            LibraryGroup::ALBUMARTIST
+                .key(a)
+                .cmp(&LibraryGroup::ALBUMARTIST.key(b))
+        })
+}
+
+fn track_order(a: &Song, b: &Song) -> Ordering {
+    compare_missing(a.disc_number(), b.disc_number())
+        .then_with(|| compare_missing(a.track_number().ok(), b.track_number().ok()))
+        .then_with(|| a.title().cmp(&b.title()))
+        .then_with(|| a.get_path().cmp(&b.get_path()))
+}
+
+impl LibraryState {
+    pub fn new() -> Self {
+        LibraryState {
+            group: LibraryGroup::ALBUMARTIST,
+            view: LibraryView::GROUPS,
+            songs: Vec::new(),
+            groups: Vec::new(),
+            albums: Vec::new(),
+            items: Vec::new(),
+            state: ListState::default(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Reads the current view from the library again
+    pub fn update_state(&mut self) {
+        let group = self.group;
+
+        let mut songs = library::shared()
+            .lock()
+            .unwrap()
+            .songs()
+            .filter(|song| match &self.view {
+                LibraryView::GROUPS => true,
+                LibraryView::ALBUMS(key) => group.key(song) == *key,
+                LibraryView::TRACKS(key, album) => {
+                    key.as_ref().is_none_or(|key| group.key(song) == *key)
+                        && Album::of(song) == *album
+                }
+            })
+            .cloned()
+            .collect::<Vec<Song>>();
+
+        songs.sort_by(track_order);
+
+        let (groups, albums, items) = match &self.view {
+            LibraryView::GROUPS if group == LibraryGroup::ALBUM => {
+                let mut albums = albums(&songs);
+
+                albums.sort_by(|(a, _), (b, _)| {
+                    let name = |album: &Album| album.name.to_lowercase();
+
+                    name(a)
+                        .cmp(&name(b))
+                        .then_with(|| a.artist.to_lowercase().cmp(&b.artist.to_lowercase()))
+                });
+
+                let items = albums
+                    .iter()
+                    .map(|(album, _)| format!("{} - {}", album.label(), album.artist))
+                    .collect();
+
+                (
+                    Vec::new(),
+                    albums.into_iter().map(|(album, _)| album).collect(),
+                    items,
+                )
+            }
+            LibraryView::GROUPS => {
+                let mut groups = songs
+                    .iter()
+                    .map(|song| group.key(song))
+                    .collect::<Vec<String>>();
+
+                groups.sort_by(|a, b| {
+                    a.to_lowercase()
+                        .cmp(&b.to_lowercase())
+                        .then_with(|| a.cmp(b))
+                });
+                groups.dedup();
+
+                (groups.clone(), Vec::new(), groups)
+            }
+            LibraryView::ALBUMS(key) => {
+                let mut albums = albums(&songs);
+
+                albums.sort_by(|(a, a_year), (b, b_year)| {
+                    compare_missing(*a_year, *b_year)
+                        .then_with(|| a.name.cmp(&b.name))
+                        .then_with(|| a.artist.cmp(&b.artist))
+                });
+
+                // grouped by genre or track artist, the album artist tells albums apart
+                let items = albums
+                    .iter()
+                    .map(|(album, year)| {
+                        let mut label = album.label();
+
+                        if album.artist != *key {
+                            label = format!("{} - {}", label, album.artist);
+                        }
+
+                        match year {
+                            Some(year) => format!("{} ({})", label, year),
+                            None => label,
+                        }
+                    })
+                    .collect();
+
+                (
+                    Vec::new(),
+                    albums.into_iter().map(|(album, _)| album).collect(),
+                    items,
+                )
+            }
+            LibraryView::TRACKS(_, _) => (
+                Vec::new(),
+                Vec::new(),
+                songs.iter().map(|song| song.title()).collect(),
+            ),
+        };
+
+        self.songs = songs;
+        self.groups = groups;
+        self.albums = albums;
+        self.items = items;
+
+        match self.state.selected() {
+            Some(i) if i < self.items.len() => {}
+            _ if self.items.is_empty() => self.state.select(None),
+            _ => self.state.select(Some(0)),
+        }
+    }
+
+    /// The song under the cursor, only in the tracks view
+    pub fn selected_song(&self) -> Option<&Song> {
+        match self.view {
+            LibraryView::TRACKS(_, _) => self.songs.get(self.state.selected()?),
+            _ => None,
+        }
+    }
+
+    /// Every song below the selected group or album, or the selected track
+    pub fn selected_songs(&self) -> Vec<Song> {
+        let selected = self.state.selected().unwrap_or_default();
+
+        if let Some(key) = self.groups.get(selected) {
+            let mut songs = self
+                .songs
+                .iter()
+                .filter(|song| self.group.key(song) == *key)
+                .cloned()
+                .collect::<Vec<Song>>();
+
+            songs.sort_by(|a, b| album_order(a, b).then_with(|| track_order(a, b)));
+
+            return songs;
+        }
+
+        if let Some(album) = self.albums.get(selected) {
+            return self
+                .songs
+                .iter()
+                .filter(|song| Album::of(song) == *album)
+                .cloned()
+                .collect();
+        }
+
+        self.selected_song().into_iter().cloned().collect()
+    }
+
+    /// Opens the selected group or album, returns false in the tracks view
+    pub fn enter(&mut self) -> bool {
+        let selected = match self.state.selected() {
+            Some(i) => i,
+            None => return false,
+        };
+
+        let view = match (
+            &self.view,
+            self.groups.get(selected),
+            self.albums.get(selected),
+        ) {
+            (LibraryView::GROUPS, Some(key), _) => LibraryView::ALBUMS(key.clone()),
+            (LibraryView::GROUPS, _, Some(album)) => LibraryView::TRACKS(None, album.clone()),
+            (LibraryView::ALBUMS(key), _, Some(album)) => {
+                LibraryView::TRACKS(Some(key.clone()), album.clone())
+            }
+            _ => return false,
+        };
+
+        self.history.push(Some(selected));
+        self.view = view;
+        self.state.select(Some(0));
+        self.update_state();
+
+        true
+    }
+
+    pub fn back(&mut self) {
+        let view = match &self.view {
+            LibraryView::GROUPS => return,
+            LibraryView::ALBUMS(_) => LibraryView::GROUPS,
+            LibraryView::TRACKS(Some(group), _) => LibraryView::ALBUMS(group.clone()),
+            LibraryView::TRACKS(None, _) => LibraryView::GROUPS,
+        };
+
+        self.view = view;
+        self.state.select(self.history.pop().flatten());
+        self.update_state();
+    }
+
+    /// Switches what the top level is grouped by and goes back to it
+    pub fn next_group(&mut self) {
+        self.group = self.group.next();
+        self.view = LibraryView::GROUPS;
+        self.history.clear();
+        self.state.select(Some(0));
+        self.update_state();
+    }
+
+    pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i >= self.items.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+
+        self.state.select(Some(i));
+    }
+
+    pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    self.items.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+
+        self.state.select(Some(i));
+    }
+}
+
+pub fn screen_library(app: &mut App, f: &mut Frame, area: Rect) -> Result<()> {
+    let layout = Layout::default()
+        .constraints(vec![Constraint::Min(1), Constraint::Max(1)])
+        .split(area);
+
+    let library = &mut app.library_state;
+
+    match &library.view {
+        LibraryView::TRACKS(_, _) => {
+            let rows = library
+                .songs
+                .iter()
+                .map(|song| {
+                    // the disc only matters for albums that have more than one
+                    let number = match (song.disc_number(), song.track_number()) {
+                        (Some(disc), Ok(track)) if song.total_discs().unwrap_or(2) > 1 => {
+                            format!("{}.{:02}", disc, track)
+                        }
+                        (_, Ok(track)) => format!("{:02}", track),
+                        _ => String::new(),
+                    };
+
+                    let style = if app.marked.contains(&song.get_path()) {
+                        Style::default().fg(Color::Yellow)
+                    } else {
+                        Style::default()
+                    };
+
+                    Row::new(vec![
+                        number,
+                        song.title(),
+                        song.artist().unwrap_or_default(),
+                        song.duration_in_minutes_and_seconds().unwrap_or_default(),
+                    ])
+                    .style(style)
+                })
+                .collect::<Vec<Row>>();
+
+            let widths = [
+                Constraint::Length(6),
+                Constraint::Percentage(50),
+                Constraint::Percentage(35),
+                Constraint::Min(8),
+            ];
+
+            let mut state = TableState::default().with_selected(library.state.selected());
+
+            f.render_stateful_widget(
+                Table::new(rows, widths)
+                    .header(Row::new(vec!["#", "Title", "Artist", "Duration"]).bold())
+                    .highlight_symbol("$ ")
+                    .highlight_style(Style::default().bg(Color::DarkGray)),
+                layout[0],
+                &mut state,
+            );
+        }
+        _ => {
+            f.render_stateful_widget(
+                List::new(library.items.clone())
+                    .highlight_symbol("$ ")
+                    .highlight_style(Style::default().bg(Color::DarkGray)),
+                layout[0],
+                &mut library.state,
+            );
+        }
+    }
+
+    let name = library.group.name();
+
+    let location = match &library.view {
+        LibraryView::GROUPS => String::from(name),
+        LibraryView::ALBUMS(group) => format!("{} / {}", name, group),
+        LibraryView::TRACKS(Some(group), album) => {
+            format!("{} / {} / {}", name, group, album.label())
+        }
+        LibraryView::TRACKS(None, album) => {
+            format!("{} / {} - {}", name, album.label(), album.artist)
+        }
+    };
+
+    let details = library
+        .selected_song()
+        .map(details)
+        .filter(|details| !details.is_empty())
+        .map(|details| format!(" - {}", details))
+        .unwrap_or_default();
+
+    f.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::raw(location),
+            Span::styled(details, Style::default().fg(Color::DarkGray)),
+        ])),
+        layout[1],
+    );
+
+    Ok(())
+}
+
+/// The tags that do not fit in the table
+fn details(song: &Song) -> String {
+    let mut details = Vec::new();
+
+    if let Some(genre) = song.genre() {
+        details.push(genre);
+    }
+
+    if let Some(year) = song.year() {
+        details.push(year.to_string());
+    }
+
+    if let (Some(disc), Some(discs)) = (song.disc_number(), song.total_discs()) {
+        details.push(format!("disc {}/{}", disc, discs));
+    }
+
+    if let (Ok(track), Some(tracks)) = (song.track_number(), song.total_tracks()) {
+        details.push(format!("track {}/{}", track, tracks));
+    }
+
+    if let Some(composer) = song.composer() {
+        details.push(format!("composed by {}", composer));
+    }
+
+    if let Some(comment) = song.comment() {
+        details.push(comment);
+    }
+
+    let inferred = song.inferred();
+    let guessed = [
+        (inferred.title, "title"),
+        (inferred.artist, "artist"),
+        (inferred.album, "album"),
+    ]
+    .into_iter()
+    .filter(|(guessed, _)| *guessed)
+    .map(|(_, field)| field)
+    .collect::<Vec<&str>>();
+
+    if !guessed.is_empty() {
+        details.push(format!("{} from the path", guessed.join(", ")));
+    }
+
+    details
+        .into_iter()
+        .filter(|detail| !detail.is_empty())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::song::SongBuilder;
+
+    fn song(path: &str, artist: Option<&str>, album: Option<&str>, year: Option<i32>) -> Song {
+        SongBuilder::new()
+            .title(String::from("Intro"))
+            .artist(artist.map(String::from))
+            .album(album.map(String::from))
+            .year(year)
+            .path(PathBuf::from(path))
+            .build()
+    }
+
+    #[test]
+    fn albums_with_the_same_title_stay_apart() {
+        let songs = [
+            song(
+                "/a/greatest/1.mp3",
+                Some("Queen"),
+                Some("Greatest Hits"),
+                Some(1981),
+            ),
+            song(
+                "/b/greatest/1.mp3",
+                Some("ABBA"),
+                Some("Greatest Hits"),
+                Some(1975),
+            ),
+            song(
+                "/a/greatest/2.mp3",
+                Some("Queen"),
+                Some("Greatest Hits"),
+                Some(1980),
+            ),
+            song("/c/loose/1.mp3", None, None, None),
+            song("/d/other/1.mp3", None, None, None),
+        ];
+
+        let albums = albums(&songs);
+
+        assert_eq!(albums.len(), 4);
+        assert_eq!(albums[0].0.artist, "Queen");
+        assert_eq!(albums[0].1, Some(1980));
+        assert_eq!(albums[1].0.artist, "ABBA");
+        assert_eq!(albums[2].0.label(), "Unknown album (loose)");
+        assert_eq!(albums[3].0.label(), "Unknown album (other)");
+    }
+}
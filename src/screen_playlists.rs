@@ -0,0 +1,253 @@
+use crate::playlist::{is_playlist_file, Playlist, PlaylistBuilder};
+use crate::song::Song;
+use crate::App;
+use color_eyre::eyre::{eyre, Result};
+use ratatui::{prelude::*, widgets::*};
+use std::fs;
+use std::path::PathBuf;
+
+pub struct PlaylistsState {
+    path: PathBuf,
+    playlists: Vec<Playlist>,
+    state: ListState,
+}
+
+impl PlaylistsState {
+    pub fn new(path: PathBuf) -> Self {
+        PlaylistsState {
+            path,
+            playlists: Vec::new(),
+            state: ListState::default(),
+        }
+    }
+
+    /// Every directory and playlist file inside the playlists directory is a playlist
+    pub fn update_state(&mut self) -> Result<()> {
+        if !self.path.is_dir() {
+            self.playlists = Vec::new();
+            self.state.select(None);
+            return Ok(());
+        }
+
+        let mut paths = fs::read_dir(&self.path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_dir() || is_playlist_file(path))
+            .collect::<Vec<PathBuf>>();
+
+        paths.sort();
+
+        self.playlists = paths
+            .iter()
+            .filter_map(|path| {
+                if path.is_dir() {
+                    PlaylistBuilder::new()
+                        .from_path(path.to_str()?)
+                        .ok()
+                        .map(|playlist| playlist.sort_by_track_number().build())
+                } else {
+                    PlaylistBuilder::new()
+                        .from_file(path)
+                        .ok()
+                        .map(|playlist| playlist.build())
+                }
+            })
+            .collect();
+
+        match self.state.selected() {
+            Some(i) if i < self.playlists.len() => {}
+            _ if self.playlists.is_empty() => self.state.select(None),
+            _ => self.state.select(Some(0)),
+        }
+
+        Ok(())
+    }
+
+    pub fn selected_playlist(&self) -> Option<&Playlist> {
+        self.playlists.get(self.state.selected()?)
+    }
+
+    /// Indices of the playlists that are backed by a file, directories can not be edited
+    pub fn editable(&self) -> Vec<usize> {
+        self.playlists
+            .iter()
+            .enumerate()
+            .filter(|(_, playlist)| playlist.file().is_some())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn playlist(&self, index: usize) -> Option<&Playlist> {
+        self.playlists.get(index)
+    }
+
+    pub fn add_songs(&mut self, index: usize, songs: Vec<Song>) -> Result<()> {
+        let playlist = match self.playlists.get_mut(index) {
+            Some(playlist) => playlist,
+            None => return Err(eyre!("Playlist does not exist")),
+        };
+
+        for song in songs {
+            playlist.add_song(song);
+        }
+
+        playlist.save()
+    }
+
+    /// New playlists are saved as extended M3U in the playlists directory
+    pub fn create(&mut self, name: &str, songs: Vec<Song>) -> Result<()> {
+        check_name(name)?;
+
+        let file = self.path.join(format!("{}.m3u8", name));
+
+        if file.exists() {
+            return Err(eyre!("{} already exists", file.display()));
+        }
+
+        fs::create_dir_all(&self.path)?;
+
+        PlaylistBuilder::new()
+            .name(name)
+            .file(file)
+            .songs(songs)
+            .build()
+            .save()?;
+
+        self.update_state()
+    }
+
+    pub fn rename_selected(&mut self, name: &str) -> Result<()> {
+        check_name(name)?;
+
+        match self.state.selected() {
+            Some(i) => self.playlists[i].rename(name),
+            None => Err(eyre!("No playlist selected")),
+        }
+    }
+
+    pub fn next(&mut self) {
+        if self.playlists.is_empty() {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i >= self.playlists.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+
+        self.state.select(Some(i));
+    }
+
+    pub fn previous(&mut self) {
+        if self.playlists.is_empty() {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    self.playlists.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+
+        self.state.select(Some(i));
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        return Err(eyre!("Name can not be empty"));
+    }
+
+    if name.contains(std::path::MAIN_SEPARATOR) || name.contains('/') {
+        return Err(eyre!("Name can not contain a path separator"));
+    }
+
+    Ok(())
+}
+
+pub fn screen_playlists(app: &mut App, f: &mut Frame, area: Rect) -> Result<()> {
+    let layout = Layout::default()
+        .constraints(vec![Constraint::Min(1), Constraint::Max(1)])
+        .split(area);
+
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Percentage(30), Constraint::Percentage(70)])
+        .split(layout[0]);
+
+    let names = app
+        .playlists_state
+        .playlists
+        .iter()
+        .map(|playlist| format!("{} ({})", playlist.name(), playlist.length()))
+        .collect::<Vec<String>>();
+
+    f.render_stateful_widget(
+        List::new(names)
+            .block(Block::new().borders(Borders::RIGHT))
+            .highlight_symbol("$ ")
+            .highlight_style(Style::default().bg(Color::DarkGray)),
+        columns[0],
+        &mut app.playlists_state.state,
+    );
+
+    let rows = match app.playlists_state.selected_playlist() {
+        Some(playlist) => playlist
+            .songs()
+            .iter()
+            .map(|song| {
+                Row::new(vec![
+                    song.title(),
+                    song.artist().unwrap_or_default(),
+                    song.duration_in_minutes_and_seconds().unwrap_or_default(),
+                ])
+            })
+            .collect::<Vec<Row>>(),
+        None => Vec::new(),
+    };
+
+    let widths = [
+        Constraint::Percentage(50),
+        Constraint::Percentage(35),
+        Constraint::Min(8),
+    ];
+
+    f.render_widget(
+        Table::new(rows, widths).header(Row::new(vec!["Title", "Artist", "Duration"]).bold()),
+        columns[1],
+    );
+
+    let missing = match app.playlists_state.selected_playlist() {
+        Some(playlist) if !playlist.missing().is_empty() => format!(
+            " - {} missing: {}",
+            playlist.missing().len(),
+            playlist
+                .missing()
+                .iter()
+                .map(|entry| entry.path.display().to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        _ => String::new(),
+    };
+
+    f.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::raw(format!("{}", app.playlists_state.path.display())),
+            Span::styled(missing, Style::default().fg(Color::Red)),
+        ])),
+        layout[1],
+    );
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use crate::song::Song;
+use crate::App;
+use color_eyre::eyre::Result;
+use ratatui::{prelude::*, widgets::*};
+use std::path::PathBuf;
+
+pub struct QueueState {
+    songs: Vec<Song>,
+    playing: Option<usize>,
+    state: TableState,
+}
+
+impl QueueState {
+    pub fn new() -> Self {
+        QueueState {
+            songs: Vec::new(),
+            playing: None,
+            state: TableState::default(),
+        }
+    }
+
+    pub fn set_queue(&mut self, songs: Vec<Song>, playing: Option<usize>) {
+        self.songs = songs;
+        self.playing = playing;
+
+        if let Some(i) = self.state.selected() {
+            if self.songs.is_empty() {
+                self.state.select(None);
+            } else if i >= self.songs.len() {
+                self.state.select(Some(self.songs.len() - 1));
+            }
+        }
+    }
+
+    pub fn set_playing(&mut self, playing: Option<usize>) {
+        self.playing = playing;
+    }
+
+    pub fn selected_song(&self) -> Option<&Song> {
+        self.songs.get(self.state.selected()?)
+    }
+
+    pub fn song(&self, path: &PathBuf) -> Option<&Song> {
+        self.songs.iter().find(|song| song.get_path() == *path)
+    }
+
+    pub fn playing_song(&self) -> Option<&Song> {
+        self.songs.get(self.playing?)
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.state.selected()
+    }
+
+    pub fn len(&self) -> usize {
+        self.songs.len()
+    }
+
+    pub fn next(&mut self) {
+        if self.songs.is_empty() {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i >= self.songs.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+
+        self.state.select(Some(i));
+    }
+
+    pub fn previous(&mut self) {
+        if self.songs.is_empty() {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    self.songs.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+
+        self.state.select(Some(i));
+    }
+}
+
+pub fn screen_queue(app: &mut App, f: &mut Frame, area: Rect) -> Result<()> {
+    let layout = Layout::default()
+        .constraints(vec![Constraint::Min(1), Constraint::Max(1)])
+        .split(area);
+
+    let rows = app
+        .queue_state
+        .songs
+        .iter()
+        .enumerate()
+        .map(|(i, song)| {
+            let marker = if app.queue_state.playing == Some(i) {
+                ">"
+            } else {
+                ""
+            };
+
+            let style = if app.marked.contains(&song.get_path()) {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+
+            Row::new(vec![
+                marker.to_string(),
+                song.title(),
+                song.artist().unwrap_or_default(),
+                song.album().unwrap_or_default(),
+                song.duration_in_minutes_and_seconds().unwrap_or_default(),
+            ])
+            .style(style)
+        })
+        .collect::<Vec<Row>>();
+
+    let widths = [
+        Constraint::Length(1),
+        Constraint::Percentage(40),
+        Constraint::Percentage(25),
+        Constraint::Percentage(25),
+        Constraint::Min(8),
+    ];
+
+    f.render_stateful_widget(
+        Table::new(rows, widths)
+            .header(Row::new(vec!["", "Title", "Artist", "Album", "Duration"]).bold())
+            .highlight_symbol("$ ")
+            .highlight_style(Style::default().bg(Color::DarkGray)),
+        layout[0],
+        &mut app.queue_state.state,
+    );
+
+    f.render_widget(
+        Paragraph::new(format!("{} songs in queue", app.queue_state.songs.len())),
+        layout[1],
+    );
+
+    Ok(())
+}
@@ -21,7 +21,7 @@ fn center_layout(text_len: u16, layout: Rect) -> Rect {
         .split(layout)[1]
 }
 
-pub fn screen_welcome(_app: &App, f: &mut Frame) -> Result<()> {
+pub fn screen_welcome(_app: &App, f: &mut Frame, area: Rect) -> Result<()> {
     let layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints(vec![
@@ -30,7 +30,7 @@ pub fn screen_welcome(_app: &App, f: &mut Frame) -> Result<()> {
             Constraint::Min(1),
             Constraint::Min(1),
         ])
-        .split(f.size());
+        .split(area);
 
     let title = "Rust Music Player";
 
@@ -47,29 +47,61 @@ pub fn screen_welcome(_app: &App, f: &mut Frame) -> Result<()> {
     let rows_controls = [
         Row::new(vec!["<p>", "Play or Pause current song"]),
         Row::new(vec!["<s>", "Skip current song"]),
+        Row::new(vec!["<.>", "Seek forward 5 seconds"]),
+        Row::new(vec!["<,>", "Seek backward 5 seconds"]),
+        Row::new(vec!["<>>", "Seek forward 30 seconds"]),
+        Row::new(vec!["<<>", "Seek backward 30 seconds"]),
+        Row::new(vec!["<t>", "Seek to a timestamp"]),
+        Row::new(vec!["</>", "Search the library"]),
+        Row::new(vec!["<+>", "Volume up"]),
+        Row::new(vec!["<->", "Volume down"]),
+        Row::new(vec!["<m>", "Mute or unmute"]),
+        Row::new(vec!["<z>", "Toggle shuffle"]),
+        Row::new(vec!["<x>", "Cycle repeat off / all / one"]),
+        Row::new(vec!["<C>", "Toggle crossfade"]),
+        Row::new(vec!["<g>", "Cycle ReplayGain off/track/album"]),
+        Row::new(vec!["<U>", "Rescan the music library"]),
         Row::new(vec!["<j>", "Move to next file"]),
         Row::new(vec!["<k>", "Move to previous line"]),
         Row::new(vec!["<l>", "Enter directory / Add song to queue"]),
         Row::new(vec!["<h>", "Go up a directory"]),
+        Row::new(vec!["<f>", "Filter directory, <esc> clears"]),
+        Row::new(vec!["<n>", "Next filter match"]),
+        Row::new(vec!["<N>", "Previous filter match"]),
+        Row::new(vec!["<P>", "Play song next"]),
+        Row::new(vec!["<d>", "Remove song from queue"]),
+        Row::new(vec!["<J>", "Move song down in queue"]),
+        Row::new(vec!["<K>", "Move song up in queue"]),
+        Row::new(vec!["<c>", "Clear queue"]),
+        Row::new(vec!["<l>", "Add playlist to queue"]),
+        Row::new(vec!["<L>", "Add artist or album to queue"]),
+        Row::new(vec!["<v>", "Group by artist / album / genre"]),
+        Row::new(vec!["<space>", "Mark song"]),
+        Row::new(vec!["<a>", "Add marked songs to a playlist"]),
+        Row::new(vec!["<G>", "Write ReplayGain tags"]),
+        Row::new(vec!["<R>", "Rename playlist"]),
     ];
 
-    let widths_controls = [Constraint::Length(3), Constraint::Length(35)];
+    let widths_controls = [Constraint::Length(7), Constraint::Length(35)];
 
     f.render_widget(
         Table::new(rows_controls, widths_controls),
-        center_layout(38, layout[2]),
+        center_layout(43, layout[2]),
     );
 
     let rows_screens = [
         Row::new(vec!["<1>", "Welcome screen"]),
+        Row::new(vec!["<2>", "Queue screen"]),
+        Row::new(vec!["<3>", "Playlists screen"]),
         Row::new(vec!["<4>", "Browser screen"]),
+        Row::new(vec!["<5>", "Library screen"]),
     ];
 
-    let widths_screens = [Constraint::Length(3), Constraint::Length(14)];
+    let widths_screens = [Constraint::Length(3), Constraint::Length(16)];
 
     f.render_widget(
         Table::new(rows_screens, widths_screens),
-        center_layout(18, layout[3]),
+        center_layout(20, layout[3]),
     );
 
     Ok(())
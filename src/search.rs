@@ -0,0 +1,290 @@
+use crate::library;
+use crate::song::Song;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use std::thread;
+
+/// Only the best matches are ranked and shown
+const MAX_RESULTS: usize = 200;
+/// Best guesses of the quick matcher that get ranked properly
+const RERANKED: usize = 4 * MAX_RESULTS;
+
+/// Fuzzy search over a snapshot of the library, taken when the search opens
+pub struct Search {
+    songs: Vec<Song>,
+    /// Title, artist, album and path of every song, matched against the query
+    haystacks: Vec<String>,
+    lowercase: Vec<String>,
+    query: String,
+    /// Every song matching the query, a longer query only has to look at these
+    matches: Vec<usize>,
+    /// Best matches first
+    results: Vec<usize>,
+    /// Skips the full scoring matrix, good enough to tell what matches
+    quick: SkimMatcherV2,
+    matcher: SkimMatcherV2,
+}
+
+impl Search {
+    pub fn new() -> Search {
+        let songs = library::shared()
+            .lock()
+            .unwrap()
+            .songs()
+            .cloned()
+            .collect::<Vec<Song>>();
+
+        Search::with_songs(songs)
+    }
+
+    fn with_songs(mut songs: Vec<Song>) -> Search {
+        songs.sort_by_key(|song| song.get_path());
+
+        let haystacks: Vec<String> = songs
+            .iter()
+            .map(|song| {
+                format!(
+                    "{} {} {} {}",
+                    song.title(),
+                    song.artist().unwrap_or_default(),
+                    song.album().unwrap_or_default(),
+                    song.get_path().display()
+                )
+            })
+            .collect();
+
+        let lowercase = haystacks
+            .iter()
+            .map(|haystack| haystack.to_lowercase())
+            .collect();
+
+        Search {
+            lowercase,
+            matches: (0..songs.len()).collect(),
+            songs,
+            haystacks,
+            query: String::new(),
+            results: Vec::new(),
+            quick: SkimMatcherV2::default().element_limit(1),
+            matcher: SkimMatcherV2::default(),
+        }
+    }
+
+    pub fn update(&mut self, query: &str) {
+        let query = query.trim();
+
+        if query.is_empty() {
+            self.query.clear();
+            self.matches = (0..self.songs.len()).collect();
+            self.results.clear();
+            return;
+        }
+
+        // whatever matches the longer query also matched the shorter one
+        let candidates = if !self.query.is_empty() && query.starts_with(&self.query) {
+            std::mem::take(&mut self.matches)
+        } else {
+            (0..self.songs.len()).collect()
+        };
+
+        let mut scored = self.score(&candidates, query);
+
+        self.matches = scored.iter().map(|(_, i)| *i).collect();
+
+        // ranking everything is wasted work when only the top is shown
+        if scored.len() > RERANKED {
+            scored.select_nth_unstable_by(RERANKED, best_first);
+            scored.truncate(RERANKED);
+        }
+
+        for (score, i) in scored.iter_mut() {
+            if let Some(full) = self.matcher.fuzzy_match(&self.haystacks[*i], query) {
+                *score = full;
+            }
+        }
+
+        scored.sort_by(best_first);
+        scored.truncate(MAX_RESULTS);
+
+        self.query = String::from(query);
+        self.results = scored.into_iter().map(|(_, i)| i).collect();
+    }
+
+    /// Scores the candidates on every core, big libraries are too slow for one
+    fn score(&self, candidates: &[usize], query: &str) -> Vec<(i64, usize)> {
+        let lowercase = query.to_lowercase();
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = candidates.len().div_ceil(threads).max(1024);
+
+        let score = |candidates: &[usize]| {
+            candidates
+                .iter()
+                .filter(|i| is_subsequence(&lowercase, &self.lowercase[**i]))
+                .filter_map(|i| {
+                    self.quick
+                        .fuzzy_match(&self.haystacks[*i], query)
+                        .map(|score| (score, *i))
+                })
+                .collect::<Vec<(i64, usize)>>()
+        };
+
+        thread::scope(|scope| {
+            let handles = candidates
+                .chunks(chunk)
+                .map(|candidates| scope.spawn(move || score(candidates)))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_default())
+                .collect()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// How many songs match, not only the ones that are shown
+    pub fn matches(&self) -> usize {
+        match self.query.is_empty() {
+            true => 0,
+            false => self.matches.len(),
+        }
+    }
+
+    pub fn song(&self, index: usize) -> Option<&Song> {
+        self.results.get(index).map(|i| &self.songs[*i])
+    }
+
+    pub fn results(&self) -> impl Iterator<Item = &Song> {
+        self.results.iter().map(|i| &self.songs[*i])
+    }
+}
+
+fn best_first(a: &(i64, usize), b: &(i64, usize)) -> std::cmp::Ordering {
+    b.0.cmp(&a.0).then(a.1.cmp(&b.1))
+}
+
+/// Cheap check that rules out most songs before the fuzzy matcher scores them
+fn is_subsequence(query: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+
+    query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .all(|c| haystack.any(|h| h == c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::song::SongBuilder;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    fn song(title: &str, artist: &str, album: &str) -> Song {
+        SongBuilder::new()
+            .title(String::from(title))
+            .artist(Some(String::from(artist)))
+            .album(Some(String::from(album)))
+            .path(PathBuf::from(format!(
+                "/music/{}/{}/{}.mp3",
+                artist, album, title
+            )))
+            .build()
+    }
+
+    fn titles(search: &Search) -> Vec<String> {
+        search.results().map(|song| song.title()).collect()
+    }
+
+    /// Made up names, so a query only matches the songs it is meant to
+    fn generated(len: usize) -> Vec<Song> {
+        let syllables = ["ka", "lo", "mi", "ne", "su", "ta", "ri", "vo", "ze", "pu"];
+        let word = |mut n: usize| {
+            let mut word = String::new();
+            for _ in 0..5 {
+                word.push_str(syllables[n % syllables.len()]);
+                n /= syllables.len();
+            }
+            word
+        };
+
+        (0..len)
+            .map(|i| song(&word(i), &word(i / 10 + 7), &word(i / 100 + 3)))
+            .collect()
+    }
+
+    #[test]
+    fn close_matches_rank_first() {
+        let mut search = Search::with_songs(vec![
+            song("Yellow Submarine", "The Beatles", "Revolver"),
+            song("Yesterday", "The Beatles", "Help!"),
+            song("Say Yes Today", "Nobody", "Somewhere"),
+            song("Hey Jude", "The Beatles", "Hey Jude"),
+        ]);
+
+        search.update("yesterday");
+        assert_eq!(titles(&search)[0], "Yesterday");
+
+        search.update("beatles jude");
+        assert_eq!(titles(&search), vec!["Hey Jude"]);
+
+        search.update("zzz");
+        assert_eq!(search.len(), 0);
+        assert_eq!(search.matches(), 0);
+    }
+
+    #[test]
+    fn longer_queries_only_narrow_the_matches() {
+        let songs = generated(5000);
+        let mut typed = Search::with_songs(songs.clone());
+        let mut previous = usize::MAX;
+
+        for end in 1..="kalo mi".len() {
+            let query = &"kalo mi"[..end];
+            typed.update(query);
+
+            let mut fresh = Search::with_songs(songs.clone());
+            fresh.update(query);
+
+            assert_eq!(typed.matches(), fresh.matches(), "query {:?}", query);
+            assert_eq!(titles(&typed), titles(&fresh), "query {:?}", query);
+            assert!(typed.matches() <= previous);
+            previous = typed.matches();
+        }
+
+        // going back looks at every song again
+        typed.update("ka");
+        let mut fresh = Search::with_songs(songs);
+        fresh.update("ka");
+        assert_eq!(typed.matches(), fresh.matches());
+    }
+
+    /// Timed in release builds only, `cargo test --release` to check it
+    #[test]
+    fn keeps_up_with_100k_songs() {
+        let songs = generated(100_000);
+        let wanted = songs[54_321].clone();
+        let query = format!("{} {}", wanted.artist().unwrap(), wanted.title());
+
+        let mut search = Search::with_songs(songs);
+        let mut slowest = Duration::ZERO;
+
+        for end in 1..=query.len() {
+            let start = Instant::now();
+            search.update(&query[..end]);
+            slowest = slowest.max(start.elapsed());
+
+            assert!(search.len() <= MAX_RESULTS);
+        }
+
+        // made up names look alike, ranking is left to the test with real ones
+        assert!(search.results().any(|song| *song == wanted));
+
+        if !cfg!(debug_assertions) {
+            assert!(slowest < Duration::from_millis(100), "{:?}", slowest);
+        }
+    }
+}
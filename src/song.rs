@@ -1,16 +1,124 @@
-use audiotags::{Album, Tag};
+use crate::format::{is_ogg, AudioFormat};
+use crate::replaygain::{ogg_comments, read_id3_tag, ReplayGain};
+use audiotags::{Album, AudioTag, FlacTag, Id3v2Tag, Tag};
 use color_eyre::eyre::{eyre, Result};
 use metadata::media_file::MediaFileMetadata;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::TimeBase;
+
+pub fn format_duration(seconds: u32) -> String {
+    format!("{}m {}s", seconds / 60, seconds % 60)
+}
+
+/// audiotags only opens MP3, MP4 and FLAC files, tags of the other formats are read here
+/// and handed to it as the tag type they share
+fn read_tag(path: &Path) -> Option<Box<dyn AudioTag + Send + Sync>> {
+    if is_ogg(path) {
+        let mut tag = metaflac::Tag::new();
+
+        for (key, value) in ogg_comments(path)? {
+            // the first value wins when a key is repeated
+            if tag.get_vorbis(&key).is_none() {
+                tag.set_vorbis(key, vec![value]);
+            }
+        }
+
+        return Some(Box::new(FlacTag::from(tag)));
+    }
+
+    match AudioFormat::detect(path) {
+        Some(format @ AudioFormat::MP3)
+        | Some(format @ AudioFormat::AAC)
+        | Some(format @ AudioFormat::WAV)
+        | Some(format @ AudioFormat::AIFF) => {
+            Some(Box::new(Id3v2Tag::from(read_id3_tag(path, format)?)))
+        }
+        _ => Tag::new().read_from_path(path).ok(),
+    }
+}
+
+/// Length from the container, or from adding up every packet when it does not say
+fn decode_duration(path: &Path) -> Option<u32> {
+    let stream = MediaSourceStream::new(Box::new(File::open(path).ok()?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?
+        .format;
+
+    let track = format.default_track()?;
+    let id = track.id;
+    let params = &track.codec_params;
+
+    let time_base = params
+        .time_base
+        .or_else(|| params.sample_rate.map(|rate| TimeBase::new(1, rate)))?;
+
+    let frames = match params.n_frames {
+        Some(frames) => frames,
+        None => {
+            let mut frames = 0;
+
+            while let Ok(packet) = format.next_packet() {
+                if packet.track_id() == id {
+                    frames += packet.dur;
+                }
+            }
+
+            frames
+        }
+    };
+
+    let time = time_base.calc_time(frames);
+
+    Some((time.seconds as f64 + time.frac).ceil() as u32)
+}
+
+/// Fields that did not come from the tags of the file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Inferred {
+    pub title: bool,
+    pub artist: bool,
+    pub album: bool,
+    /// Measured by reading through the file
+    pub duration: bool,
+}
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Song {
     title: String,
+    /// Artist of the track itself, falls back to the album artist
     artist: Option<String>,
+    album_artist: Option<String>,
     album: Option<String>,
     track_number: Option<u16>,
+    total_tracks: Option<u16>,
+    disc_number: Option<u16>,
+    total_discs: Option<u16>,
+    year: Option<i32>,
+    genre: Option<String>,
+    composer: Option<String>,
+    comment: Option<String>,
     duration: Option<u32>,
+    replay_gain: ReplayGain,
+    inferred: Inferred,
     path: PathBuf,
 }
 
@@ -18,10 +126,21 @@ pub struct Song {
 #[derive(Default)]
 pub struct SongBuilder {
     title: String,
+    /// Artist of the track itself, falls back to the album artist
     artist: Option<String>,
+    album_artist: Option<String>,
     album: Option<String>,
     track_number: Option<u16>,
+    total_tracks: Option<u16>,
+    disc_number: Option<u16>,
+    total_discs: Option<u16>,
+    year: Option<i32>,
+    genre: Option<String>,
+    composer: Option<String>,
+    comment: Option<String>,
     duration: Option<u32>,
+    replay_gain: ReplayGain,
+    inferred: Inferred,
     path: PathBuf,
 }
 
@@ -40,7 +159,7 @@ impl Song {
             None => return Err(eyre!("Song does not have a duration")),
         };
 
-        Ok(format!("{}m {}s", duration / 60, duration % 60))
+        Ok(format_duration(duration))
     }
 
     // pub fn print(&self) {
@@ -57,6 +176,58 @@ impl Song {
         self.title.clone()
     }
 
+    pub fn artist(&self) -> Option<String> {
+        self.artist.clone()
+    }
+
+    pub fn album_artist(&self) -> Option<String> {
+        self.album_artist.clone()
+    }
+
+    pub fn album(&self) -> Option<String> {
+        self.album.clone()
+    }
+
+    pub fn duration(&self) -> Option<u32> {
+        self.duration
+    }
+
+    pub fn total_tracks(&self) -> Option<u16> {
+        self.total_tracks
+    }
+
+    pub fn disc_number(&self) -> Option<u16> {
+        self.disc_number
+    }
+
+    pub fn total_discs(&self) -> Option<u16> {
+        self.total_discs
+    }
+
+    pub fn year(&self) -> Option<i32> {
+        self.year
+    }
+
+    pub fn genre(&self) -> Option<String> {
+        self.genre.clone()
+    }
+
+    pub fn composer(&self) -> Option<String> {
+        self.composer.clone()
+    }
+
+    pub fn comment(&self) -> Option<String> {
+        self.comment.clone()
+    }
+
+    pub fn replay_gain(&self) -> &ReplayGain {
+        &self.replay_gain
+    }
+
+    pub fn inferred(&self) -> Inferred {
+        self.inferred
+    }
+
     pub fn track_number(&self) -> Result<&u16> {
         match &self.track_number {
             Some(num) => Ok(num),
@@ -64,6 +235,33 @@ impl Song {
         }
     }
 
+    /// Replaces fields that were guessed from the path with ones known from elsewhere
+    pub fn with_known(
+        mut self,
+        title: Option<String>,
+        artist: Option<String>,
+        album: Option<String>,
+        duration: Option<u32>,
+    ) -> Song {
+        if let (true, Some(title)) = (self.inferred.title, title) {
+            self.title = title;
+        }
+
+        if let (true, Some(artist)) = (self.inferred.artist, artist) {
+            self.artist = Some(artist);
+        }
+
+        if let (true, Some(album)) = (self.inferred.album, album) {
+            self.album = Some(album);
+        }
+
+        if self.duration.is_none() {
+            self.duration = duration;
+        }
+
+        self
+    }
+
     pub fn get_path(&self) -> PathBuf {
         self.path.clone()
     }
@@ -93,85 +291,295 @@ impl SongBuilder {
         SongBuilder {
             title: String::from(""),
             artist: Some(String::from("")),
+            album_artist: None,
             album: Some(String::from("")),
             track_number: Some(0),
+            total_tracks: None,
+            disc_number: None,
+            total_discs: None,
+            year: None,
+            genre: None,
+            composer: None,
+            comment: None,
             duration: Some(0),
+            replay_gain: ReplayGain::default(),
+            inferred: Inferred::default(),
             path: PathBuf::new(),
         }
     }
 
-    pub fn from_path(mut self, path: &PathBuf) -> Result<SongBuilder> {
+    pub fn from_path(mut self, path: &Path) -> Result<SongBuilder> {
         if !path.exists() {
             return Err(eyre!("Song: Path does not exist"));
         }
 
-        self.path = path.clone();
+        self.path = path.to_path_buf();
 
-        let tag = Tag::new().read_from_path(path).unwrap();
+        // untagged files are still songs, anything missing is inferred below
+        if let Some(tag) = read_tag(path) {
+            self.title = tag.title().map(String::from).unwrap_or_default();
+            self.track_number = tag.track_number();
+            self.total_tracks = tag.total_tracks();
+            self.disc_number = tag.disc_number();
+            self.total_discs = tag.total_discs();
+            self.year = tag.year().or_else(|| tag.date().map(|date| date.year));
+            self.genre = tag.genre().map(String::from);
+            self.composer = tag.composer().map(String::from);
+            self.comment = tag.comment().map(String::from);
 
-        self.title = match tag.title() {
-            Some(title) => title.to_string(),
-            None => return Err(eyre!("Song: title")),
-        };
+            let album: Option<Album<'_>> = tag.album();
 
-        self.track_number = match tag.track_number() {
-            Some(t) => Some(t),
-            None => None,
-        };
+            self.album = album.as_ref().map(|album| album.title.to_string());
 
-        let album: Option<Album<'_>> = match tag.album() {
-            Some(album) => Some(album),
-            None => None,
-        };
+            self.album_artist = tag
+                .album_artist()
+                .or(album.and_then(|album| album.artist))
+                .map(String::from);
 
-        self.album = match &album {
-            Some(album) => Some(album.title.to_string()),
-            None => None,
-        };
+            self.artist = tag.artist().map(String::from).or(self.album_artist.clone());
+        } else {
+            self.artist = None;
+            self.album = None;
+            self.track_number = None;
+        }
 
-        match album {
-            Some(album) => {
-                match album.artist {
-                    Some(artist) => self.artist = Some(artist.to_owned()),
-                    None => return Err(eyre!("Song: artist")),
-                };
-            }
-            None => {}
-        }
-
-        let duration: Option<Result<MediaFileMetadata, std::io::Error>> =
-            match MediaFileMetadata::new(&self.path) {
-                Ok(media) => Some(Ok(media)),
-                Err(_) => None,
-            };
-
-        self.duration = match &duration {
-            Some(duration) => match duration {
-                Ok(duration) => match duration._duration {
-                    Some(duration) => Some(duration.ceil() as u32),
-                    None => None,
-                },
-                Err(_e) => None,
-            },
-            None => None,
-        };
+        self.infer_from_path();
 
-        match duration.unwrap().unwrap()._duration {
-            Some(duration) => self.duration = Some(duration.ceil() as u32),
-            None => return Err(eyre!("Song: duration")),
+        self.duration = MediaFileMetadata::new(&self.path)
+            .ok()
+            .and_then(|media| media._duration)
+            .map(|duration| duration.ceil() as u32)
+            .filter(|duration| *duration > 0);
+
+        if self.duration.is_none() {
+            self.duration = decode_duration(path);
+            self.inferred.duration = self.duration.is_some();
         }
 
+        self.replay_gain = ReplayGain::read(path);
+
         Ok(self)
     }
 
+    /// The title comes from the file name, the album and artist from the folders above it
+    fn infer_from_path(&mut self) {
+        let missing = |value: &Option<String>| value.as_ref().is_none_or(|v| v.trim().is_empty());
+
+        let folders = self
+            .path
+            .ancestors()
+            .skip(1)
+            .filter_map(|dir| dir.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+
+        if self.title.trim().is_empty() {
+            if let Some(stem) = self.path.file_stem() {
+                self.title = stem.to_string_lossy().to_string();
+                self.inferred.title = true;
+            }
+        }
+
+        // only what could be read from a folder counts as inferred
+        if missing(&self.album) {
+            self.album = folders.first().cloned();
+            self.inferred.album = self.album.is_some();
+        }
+
+        if missing(&self.artist) {
+            self.artist = folders.get(1).cloned();
+            self.inferred.artist = self.artist.is_some();
+        }
+    }
+
+    pub fn title(mut self, title: String) -> SongBuilder {
+        self.title = title;
+        self
+    }
+
+    pub fn artist(mut self, artist: Option<String>) -> SongBuilder {
+        self.artist = artist;
+        self
+    }
+
+    pub fn album_artist(mut self, album_artist: Option<String>) -> SongBuilder {
+        self.album_artist = album_artist;
+        self
+    }
+
+    pub fn album(mut self, album: Option<String>) -> SongBuilder {
+        self.album = album;
+        self
+    }
+
+    pub fn year(mut self, year: Option<i32>) -> SongBuilder {
+        self.year = year;
+        self
+    }
+
+    pub fn duration(mut self, duration: Option<u32>) -> SongBuilder {
+        self.duration = duration;
+        self
+    }
+
+    pub fn replay_gain(mut self, replay_gain: ReplayGain) -> SongBuilder {
+        self.replay_gain = replay_gain;
+        self
+    }
+
+    pub fn inferred(mut self, inferred: Inferred) -> SongBuilder {
+        self.inferred = inferred;
+        self
+    }
+
+    pub fn path(mut self, path: PathBuf) -> SongBuilder {
+        self.path = path;
+        self
+    }
+
     pub fn build(self) -> Song {
         Song {
             title: self.title,
             artist: self.artist,
+            album_artist: self.album_artist,
             album: self.album,
             track_number: self.track_number,
+            total_tracks: self.total_tracks,
+            disc_number: self.disc_number,
+            total_discs: self.total_discs,
+            year: self.year,
+            genre: self.genre,
+            composer: self.composer,
+            comment: self.comment,
             duration: self.duration,
+            replay_gain: self.replay_gain,
+            inferred: self.inferred,
             path: self.path,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn untagged(path: &str) -> SongBuilder {
+        SongBuilder::new()
+            .title(String::new())
+            .artist(None)
+            .album(None)
+            .path(PathBuf::from(path))
+    }
+
+    #[test]
+    fn missing_tags_come_from_the_path() {
+        let mut builder = untagged("/music/Nina Simone/Pastel Blues/05 Sinnerman.flac");
+        builder.infer_from_path();
+        let song = builder.build();
+
+        assert_eq!(song.title(), "05 Sinnerman");
+        assert_eq!(song.album().as_deref(), Some("Pastel Blues"));
+        assert_eq!(song.artist().as_deref(), Some("Nina Simone"));
+
+        let inferred = song.inferred();
+        assert!(inferred.title && inferred.album && inferred.artist);
+    }
+
+    #[test]
+    fn tags_are_kept_and_missing_folders_are_not_inferred() {
+        let mut builder = untagged("/Sinnerman.flac").album(Some(String::from("Pastel Blues")));
+        builder.infer_from_path();
+        let song = builder.build();
+
+        assert_eq!(song.title(), "Sinnerman");
+        assert_eq!(song.album().as_deref(), Some("Pastel Blues"));
+        assert_eq!(song.artist(), None);
+
+        let inferred = song.inferred();
+        assert!(inferred.title);
+        assert!(!inferred.album);
+        assert!(!inferred.artist);
+    }
+
+    #[test]
+    fn known_fields_only_replace_inferred_ones() {
+        let song = untagged("/music/Folder/track.mp3")
+            .artist(Some(String::from("Tagged")))
+            .duration(None)
+            .inferred(Inferred {
+                title: true,
+                artist: false,
+                album: true,
+                duration: false,
+            })
+            .build()
+            .with_known(
+                Some(String::from("Title")),
+                Some(String::from("Playlist artist")),
+                Some(String::from("Album")),
+                Some(215),
+            );
+
+        assert_eq!(song.title(), "Title");
+        assert_eq!(song.artist().as_deref(), Some("Tagged"));
+        assert_eq!(song.album().as_deref(), Some("Album"));
+        assert_eq!(song.duration(), Some(215));
+
+        let untouched = untagged("/music/track.mp3")
+            .title(String::from("Tagged"))
+            .build()
+            .with_known(Some(String::from("Title")), None, None, None);
+
+        assert_eq!(untouched.title(), "Tagged");
+    }
+
+    #[test]
+    fn unreadable_files_have_no_measured_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.mp3");
+        std::fs::write(&path, b"not really audio").unwrap();
+
+        let song = SongBuilder::new().from_path(&path).unwrap().build();
+
+        assert_eq!(song.duration(), None);
+        assert!(!song.inferred().duration);
+        assert_eq!(song.title(), "broken");
+    }
+
+    #[test]
+    fn reads_tags_of_ogg_and_wav_files() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let opus = dir.path().join("track.opus");
+        let tags = ["title=Hoppípolla", "ARTIST=Sigur Rós", "ALBUM=Takk..."];
+        std::fs::write(&opus, crate::opus::tests::encode(2, 5, &tags)).unwrap();
+
+        let song = SongBuilder::new().from_path(&opus).unwrap().build();
+        assert_eq!(song.title(), "Hoppípolla");
+        assert_eq!(song.artist().as_deref(), Some("Sigur Rós"));
+        assert_eq!(song.album().as_deref(), Some("Takk..."));
+        assert!(!song.inferred().title && !song.inferred().artist);
+
+        let wav = dir.path().join("track.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&wav, spec).unwrap();
+        for _ in 0..800 {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut tag = id3::Tag::new();
+        id3::TagLike::set_title(&mut tag, "Glósóli");
+        id3::TagLike::set_artist(&mut tag, "Sigur Rós");
+        tag.write_to_wav_path(&wav, id3::Version::Id3v24).unwrap();
+
+        let song = SongBuilder::new().from_path(&wav).unwrap().build();
+        assert_eq!(song.title(), "Glósóli");
+        assert_eq!(song.artist().as_deref(), Some("Sigur Rós"));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::player::PlayerState;
+use crate::queue::RepeatMode;
+use crate::replaygain::GainMode;
+use crate::song::{format_duration, Song};
+use crate::App;
+use ratatui::{prelude::*, widgets::*};
+
+pub fn status_bar(app: &App, f: &mut Frame, area: Rect) {
+    let block = Block::new().borders(Borders::TOP);
+
+    let mut constraints = vec![Constraint::Length(1), Constraint::Length(1)];
+    constraints.extend(app.scans().map(|_| Constraint::Length(1)));
+
+    let layout = Layout::default()
+        .constraints(constraints)
+        .split(block.inner(area));
+
+    f.render_widget(block, area);
+
+    for ((name, scan), area) in app.scans().zip(layout.iter().skip(2)) {
+        let file = scan
+            .current
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        f.render_widget(
+            Gauge::default()
+                .ratio(scan.ratio())
+                .label(format!("{} {}/{} {}", name, scan.done, scan.total, file))
+                .gauge_style(Style::default().fg(Color::DarkGray)),
+            *area,
+        );
+    }
+
+    let mut flags = Vec::new();
+
+    if app.shuffle {
+        flags.push(String::from("[shuffle]"));
+    }
+
+    match app.repeat {
+        RepeatMode::OFF => {}
+        RepeatMode::ONE => flags.push(String::from("[repeat one]")),
+        RepeatMode::ALL => flags.push(String::from("[repeat all]")),
+    }
+
+    match app.state.gain_mode {
+        GainMode::OFF => {}
+        GainMode::TRACK => flags.push(String::from("[rg track]")),
+        GainMode::ALBUM => flags.push(String::from("[rg album]")),
+    }
+
+    if app.state.crossfade {
+        flags.push(String::from("[crossfade]"));
+    }
+
+    if app.state.muted {
+        flags.push(String::from("muted"));
+    } else {
+        flags.push(format!("vol {}%", (app.state.volume * 100.0).round()));
+    }
+
+    let flags = flags.join(" ");
+
+    let info = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![
+            Constraint::Min(1),
+            Constraint::Length(flags.len() as u16 + 1),
+        ])
+        .split(layout[0]);
+
+    f.render_widget(Paragraph::new(flags).alignment(Alignment::Right), info[1]);
+
+    let song = app.queue_state.playing_song();
+
+    let line = match (&app.error, song) {
+        (Some(error), _) => Line::styled(error.as_str(), Style::default().fg(Color::Red)),
+        (None, Some(song)) => now_playing(app, song),
+        (None, None) => Line::raw("Nothing playing"),
+    };
+
+    f.render_widget(Paragraph::new(line), info[0]);
+
+    let song = match song {
+        Some(song) => song,
+        None => return,
+    };
+
+    let elapsed = app.position.as_secs() as u32;
+
+    let (ratio, total) = match song.duration() {
+        Some(duration) if duration > 0 => (
+            (elapsed as f64 / duration as f64).clamp(0.0, 1.0),
+            song.duration_in_minutes_and_seconds().unwrap_or_default(),
+        ),
+        _ => (0.0, String::from("?")),
+    };
+
+    f.render_widget(
+        Gauge::default()
+            .ratio(ratio)
+            .label(format!("{} / {}", format_duration(elapsed), total))
+            .gauge_style(Style::default().fg(Color::DarkGray)),
+        layout[1],
+    );
+}
+
+fn now_playing<'a>(app: &App, song: &Song) -> Line<'a> {
+    let symbol = match app.player_state {
+        PlayerState::PLAYING => ">",
+        PlayerState::PAUSED => "||",
+    };
+
+    let mut spans = vec![
+        Span::raw(format!("{} ", symbol)),
+        Span::styled(song.title(), Style::default().bold()),
+    ];
+
+    for tag in [song.artist(), song.album()].into_iter().flatten() {
+        if !tag.is_empty() {
+            spans.push(Span::raw(format!(" - {}", tag)));
+        }
+    }
+
+    Line::from(spans)
+}
@@ -0,0 +1,174 @@
+use crate::playlist::{relative_path, PlaylistEntry};
+use crate::song::Song;
+use color_eyre::eyre::{eyre, Result};
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Locations are URIs, relative ones are resolved against the directory of the playlist
+fn location_to_path(base: &Url, location: &str) -> Option<PathBuf> {
+    base.join(location).ok()?.to_file_path().ok()
+}
+
+fn path_to_location(base: &Url, dir: &Path, path: &Path) -> Option<String> {
+    let url = Url::from_file_path(path).ok()?;
+
+    if relative_path(dir, path).is_relative() {
+        base.make_relative(&url)
+    } else {
+        Some(url.to_string())
+    }
+}
+
+fn base_url(dir: &Path) -> Result<Url> {
+    let dir = if dir.is_absolute() {
+        dir.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(dir)
+    };
+
+    Url::from_directory_path(&dir).map_err(|_e| eyre!("XSPF: invalid path {}", dir.display()))
+}
+
+pub fn read(path: &Path) -> Result<Vec<PlaylistEntry>> {
+    let contents = fs::read_to_string(path)?;
+    let base = base_url(path.parent().unwrap_or(Path::new("")))?;
+
+    let mut reader = Reader::from_str(&contents);
+    reader.trim_text(true);
+
+    let mut entries = Vec::new();
+    let mut track: Option<PlaylistEntry> = None;
+    let mut field: Vec<u8> = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(tag) => match tag.local_name().as_ref() {
+                b"track" => track = Some(PlaylistEntry::new(PathBuf::new())),
+                name => field = name.to_vec(),
+            },
+            Event::Text(text) => {
+                let text = text.unescape()?.to_string();
+
+                if let Some(entry) = track.as_mut() {
+                    match field.as_slice() {
+                        b"location" => {
+                            if let Some(path) = location_to_path(&base, &text) {
+                                entry.path = path;
+                            }
+                        }
+                        b"title" => entry.title = Some(text),
+                        b"creator" => entry.artist = Some(text),
+                        b"album" => entry.album = Some(text),
+                        // milliseconds
+                        b"duration" => {
+                            entry.duration =
+                                text.parse::<u64>().ok().map(|d| ((d + 500) / 1000) as u32)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::End(tag) => {
+                if tag.local_name().as_ref() == b"track" {
+                    if let Some(entry) = track.take() {
+                        if !entry.path.as_os_str().is_empty() {
+                            entries.push(entry);
+                        }
+                    }
+                }
+
+                field.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn write(path: &Path, title: &str, songs: &[Song]) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let base = base_url(dir)?;
+
+    let mut contents = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    contents.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    contents.push_str(&format!("  <title>{}</title>\n", escape(title)));
+    contents.push_str("  <trackList>\n");
+
+    for song in songs {
+        contents.push_str("    <track>\n");
+
+        if let Some(location) = path_to_location(&base, dir, &song.get_path()) {
+            contents.push_str(&format!(
+                "      <location>{}</location>\n",
+                escape(&location)
+            ));
+        }
+
+        contents.push_str(&format!("      <title>{}</title>\n", escape(&song.title())));
+
+        if let Some(artist) = song.artist().filter(|artist| !artist.is_empty()) {
+            contents.push_str(&format!("      <creator>{}</creator>\n", escape(&artist)));
+        }
+
+        if let Some(album) = song.album().filter(|album| !album.is_empty()) {
+            contents.push_str(&format!("      <album>{}</album>\n", escape(&album)));
+        }
+
+        if let Some(duration) = song.duration() {
+            contents.push_str(&format!(
+                "      <duration>{}</duration>\n",
+                duration as u64 * 1000
+            ));
+        }
+
+        contents.push_str("    </track>\n");
+    }
+
+    contents.push_str("  </trackList>\n");
+    contents.push_str("</playlist>\n");
+
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::tests::fixture;
+
+    #[test]
+    fn reads_fixture() {
+        let entries = read(&fixture("playlist.xspf")).unwrap();
+        let dir = fixture("playlist.xspf").parent().unwrap().to_path_buf();
+
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(
+            entries[0],
+            PlaylistEntry {
+                path: dir.join(
+                    "Boards of Canada/Music Has the Right to Children/01 Wildlife Analysis.mp3"
+                ),
+                title: Some(String::from("Wildlife Analysis")),
+                artist: Some(String::from("Boards of Canada")),
+                album: Some(String::from("Music Has the Right to Children")),
+                duration: Some(77),
+            }
+        );
+
+        assert_eq!(
+            entries[1].path,
+            PathBuf::from("/music/Sigur Rós/Takk.../02 Glósóli.flac")
+        );
+        assert_eq!(entries[1].title.as_deref(), Some("Glósóli"));
+
+        assert_eq!(entries[2], PlaylistEntry::new(dir.join("Untitled.ogg")));
+    }
+}